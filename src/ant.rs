@@ -1,18 +1,11 @@
 use ggez::*;
 use std::collections::HashSet;
-use ggez::{nalgebra as na};
-use ggez::graphics::{self, DrawMode, Mesh};
-use ggez::event::{EventHandler, KeyCode, KeyMods};
-use ggez::input::keyboard;
+use nalgebra as na;
+use ggez::graphics::{self, DrawMode, Mesh, MeshBatch};
 
 use crate::lib::*;
 
-const PURPLE: [f32; 4] = [0.4, 0.0, 0.2, 1.0];
-const PAPER: [f32; 4] = [0.8, 0.8, 0.6, 1.0];
-const RED: [f32; 4] = [0.8, 0.0, 0.0, 1.0];
-const BLUE: [f32; 4] = [0.2, 0.0, 0.5, 1.0];
-const BLACK: [f32; 4] = [0.05, 0.0, 0.0, 1.0];
-
+#[derive(Clone, Copy)]
 pub enum Orientation {
     Up,
     Down,
@@ -20,11 +13,76 @@ pub enum Orientation {
     Right,
 }
 
+// сколько шагов назад может откатить чувствующий муравей
+const HISTORY: usize = 1 << 20;
+
 pub struct Ant {
     coord: (i64, i64),
     or: Orientation
 }
 
+// Шумный муравей: поворот на каждом цвете выбирается случайно,
+// а клетка может не перекраситься. Случайность шага берётся из зерна и номера шага.
+#[derive(Clone)]
+pub struct Noise {
+    // вероятности повернуть налево, пойти прямо, направо и назад: на белой клетке и на чёрной
+    pub turns: [[f64; 4]; 2],
+    // с какой вероятностью клетка остаётся прежнего цвета
+    pub flip_failure: f64,
+}
+
+impl Noise {
+    // обычный муравей
+    pub fn none() -> Noise {
+        Noise {
+            turns: [[0.0, 0.0, 1.0, 0.0], [1.0, 0.0, 0.0, 0.0]],
+            flip_failure: 0.0,
+        }
+    }
+}
+
+// какие клетки вокруг видит муравей, кроме клетки под собой
+#[derive(Clone, Copy, PartialEq)]
+pub enum Neighbourhood {
+    Under,
+    Ahead,
+    Sides,
+    Moore,
+}
+
+impl Neighbourhood {
+    // соседи по порядку: сколько клеток вперёд и сколько вправо от муравья
+    pub fn offsets(self) -> &'static [(i64, i64)] {
+        match self {
+            Neighbourhood::Under => &[],
+            Neighbourhood::Ahead => &[(1, 0)],
+            Neighbourhood::Sides => &[(0, -1), (0, 1)],
+            // по часовой, начиная с клетки впереди
+            Neighbourhood::Moore => &[(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)],
+        }
+    }
+}
+
+// Чувствующий муравей: решение по клетке под собой и соседям.
+// Конфигурация — число, где бит 0 — клетка под муравьём, а бит k — k-й сосед, 1 — чёрная.
+#[derive(Clone)]
+pub struct Sensing {
+    pub neighbourhood: Neighbourhood,
+    // для каждой конфигурации: на сколько четвертей повернуть по часовой и перекрашивать ли
+    pub table: Vec<(u8, bool)>,
+}
+
+impl Sensing {
+    // обычный муравей, которому окрестность безразлична
+    pub fn classic(neighbourhood: Neighbourhood) -> Sensing {
+        let size = 1 << (1 + neighbourhood.offsets().len());
+        Sensing {
+            neighbourhood,
+            table: (0..size).map(|config| (if config & 1 == 1 { 3 } else { 1 }, true)).collect(),
+        }
+    }
+}
+
 struct Assets {
     black_cell: Mesh,
     white_cell: Mesh,
@@ -38,80 +96,181 @@ struct Assets {
     left_up: Mesh,
     right_down: Mesh,
     right_up: Mesh,
+    marker: Mesh,
+    tile: Mesh,
 }
 
-pub struct Screen {
-    assets: Assets,
-    resolution: (f32, f32),
-    dim: (i64, i64),
-    cell_size: f32,
-    center_coord: (i64, i64),
-}
-
-enum Cells {
-    Some(Vec<(i64, i64)>),
-    All,
-}
-
-pub struct Update {
-    cells: Cells,
-    ant: bool,
-}
+// клетки копятся в батчах и рисуются одним вызовом на каждый меш:
+// сначала клетки, потом дороги поверх них
+const TILE: usize = 0;
+const BLACK_CELL: usize = 1;
+const WHITE_CELL: usize = 2;
+const RIGHT_ROADS: usize = 3;
+const LEFT_ROADS: usize = 4;
 
 pub struct AntWalker {
-    screen: Screen,
-    update: Update,
+    screen: Screen<Assets>,
     board: HashSet<(i64, i64)>,
+    chunks: ChunkCache,
+    explored: Bounds,
+    visits: Visits,
+    changes: Changes<(i64, i64)>,
+    // сколько шагов сделано, для раскраски по давности
+    time: u64,
+    // на каком шаге доска была нарисована в последний раз
+    drawn_at: u64,
+    render: RenderMode,
     ant: Ant,
+    noise: Option<Noise>,
+    seed: u64,
+    sensing: Option<Sensing>,
+    // где стоял чувствующий муравей, куда смотрел и какой была клетка, если он её перекрасил:
+    // по соседям назад не восстановить, а после правок доски путь назад ещё и другой
+    history: Vec<((i64, i64), Orientation, Option<bool>)>,
+    // в областях карты муравей ходит по их правилам, а не по своему
+    rule_map: Option<RuleMap>,
 }
 
 impl AntWalker {
+    pub fn noisy(noise: Noise, resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> AntWalker {
+        AntWalker {
+            noise: Some(noise),
+            ..AntWalker::new(resolution, dim, theme, ctx)
+        }
+    }
+
+    pub fn sensing(sensing: Sensing, resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> AntWalker {
+        AntWalker {
+            sensing: Some(sensing),
+            ..AntWalker::new(resolution, dim, theme, ctx)
+        }
+    }
+
+    // конфигурация клеток вокруг муравья, как в Sensing::table
+    fn sense(&self, neighbourhood: Neighbourhood) -> usize {
+        let (i, j) = self.ant.coord;
+        let ahead = match self.ant.or {
+            Orientation::Up => (0, -1),
+            Orientation::Right => (1, 0),
+            Orientation::Down => (0, 1),
+            Orientation::Left => (-1, 0),
+        };
+        let right = (-ahead.1, ahead.0);
+
+        let mut config = self.board.contains(&(i, j)) as usize;
+        for (k, &(a, r)) in neighbourhood.offsets().iter().enumerate() {
+            let cell = (i + a * ahead.0 + r * right.0, j + a * ahead.1 + r * right.1);
+            if self.board.contains(&cell) {
+                config |= 1 << (k + 1);
+            }
+        }
+        config
+    }
+
+    // что велит карта правил в клетке под муравьём
+    fn mapped(&self, black: bool) -> Option<(u8, bool)> {
+        let rule = self.rule_map.as_ref()?.rule_at(self.ant.coord)?;
+        Some((quarters(rule.chars().nth(black as usize).unwrap()), true))
+    }
+
+    // что муравей делает на шаге time, стоя на клетке такого цвета:
+    // на сколько четвертей повернуть по часовой и перекрашивать ли клетку
+    fn decide(&self, black: bool, time: u64) -> (u8, bool) {
+        if let Some(decision) = self.mapped(black) {
+            return decision;
+        }
+        let noise = match &self.noise {
+            Some(noise) => noise,
+            None => return (if black { 3 } else { 1 }, true),
+        };
+
+        let mut rng = Rng::stream(self.seed, time);
+        // перекраска тянется первой: шагу назад она нужна раньше, чем цвет
+        let flip = !rng.chance(noise.flip_failure);
+
+        let weights = noise.turns[black as usize];
+        let mut left = rng.next_f64() * weights.iter().sum::<f64>();
+        let mut turn = 0;
+        for (k, &weight) in weights.iter().enumerate() {
+            if left < weight {
+                turn = k;
+                break;
+            }
+            left -= weight;
+        }
+
+        // налево, прямо, направо, назад
+        ([3, 0, 1, 2][turn], flip)
+    }
+
+    fn turn(&mut self, quarters: u8) {
+        for _ in 0..quarters {
+            self.ant.or = match self.ant.or {
+                Orientation::Up => Orientation::Right,
+                Orientation::Right => Orientation::Down,
+                Orientation::Down => Orientation::Left,
+                Orientation::Left => Orientation::Up,
+            };
+        }
+    }
+
     fn board_to_screen(&self, board_i: i64, board_j: i64) -> (i64, i64) {
-        // кординаты центра доски с точки зрения верхнего левого угла
-        let (cx, cy) = ((self.screen.dim.0 / 2) as i64, (self.screen.dim.1 / 2) as i64);
-        // координаты центра с точки зрения (0, 0) доски
-        let (cent_x, cent_y) = self.screen.center_coord;
-        (board_i + cx - cent_x, board_j + cy - cent_y)
+        self.screen.camera.board_to_screen(board_i, board_j)
     }
 
     fn screen_to_board(&self, screen_i: i64, screen_j: i64) -> (i64, i64) {
-        // кординаты центра доски с точки зрения верхнего левого угла
-        let (cx, cy) = ((self.screen.dim.0 / 2) as i64, (self.screen.dim.1 / 2) as i64);
-        // координаты центра с точки зрения (0, 0) доски
-        let (cent_x, cent_y) = self.screen.center_coord;
-        (screen_i - cx + cent_x, screen_j - cy + cent_y)
+        self.screen.camera.screen_to_board(screen_i, screen_j)
     }
 
-    fn draw_cell(&self, ctx: &mut Context, screen_i: i64, screen_j: i64) -> GameResult {
-        let cell_size = self.screen.cell_size;
+    fn draw_cell(&mut self, screen_i: i64, screen_j: i64) {
         let board_idxes = self.screen_to_board(screen_i, screen_j);
         let (board_i, board_j) = self.screen_to_board(screen_i, screen_j);
+        let param = self.screen.at(screen_i, screen_j);
+
+        if self.render != RenderMode::Board {
+            let color = self.tint(board_idxes);
+            self.screen.batches[TILE].add(param.color(color.into()));
+            return;
+        }
 
         let mut roads_switch: i64 = 0;
         roads_switch += (board_i + board_j) % 2;
 
         if self.board.contains(&board_idxes) {
             roads_switch += 1;
-            graphics::draw(ctx, &self.screen.assets.black_cell, graphics::DrawParam::default()
-            .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+            self.screen.batches[BLACK_CELL].add(param);
         } else {
-            graphics::draw(ctx, &self.screen.assets.white_cell, graphics::DrawParam::default()
-            .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+            self.screen.batches[WHITE_CELL].add(param);
         }
 
         if roads_switch % 2 == 0 {
-            graphics::draw(ctx, &self.screen.assets.right_roads, graphics::DrawParam::default()
-            .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+            self.screen.batches[RIGHT_ROADS].add(param);
         } else {
-            graphics::draw(ctx, &self.screen.assets.left_roads, graphics::DrawParam::default()
-            .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+            self.screen.batches[LEFT_ROADS].add(param);
         }
+    }
 
-        Ok(())
+    // цвет клетки в мелком масштабе и в режимах раскраски
+    fn tint(&self, cell: (i64, i64)) -> [f32; 4] {
+        let theme = &self.screen.theme;
+        match self.render {
+            RenderMode::Board => if self.board.contains(&cell) { theme.ink } else { theme.paper },
+            RenderMode::Visits => match self.visits.get(cell) {
+                0 => theme.paper,
+                n => heat_color(n, self.visits.max),
+            },
+            RenderMode::Recency => match self.changes.get(&cell) {
+                Some((changed, _)) => recency_color(changed, self.time, theme.walker, theme.paper),
+                None => theme.paper,
+            },
+            RenderMode::Flips => match self.changes.get(&cell) {
+                Some((_, flips)) if flips > 0 => heat_color(flips, self.changes.max_flips),
+                _ => theme.paper,
+            },
+        }
     }
 
-    fn draw_ant(&self, ctx: &mut Context) -> GameResult {
-        let cell_size = self.screen.cell_size;
+    fn draw_ant(&self, frame: &mut Frame) {
         let (board_i, board_j) = self.ant.coord;
         let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
         // чётные - приходим сверху-снизу, уходим вправо-влево
@@ -120,79 +279,74 @@ impl AntWalker {
             // белый
             match self.ant.or {
                 Orientation::Up => {
-                    graphics::draw(ctx, &self.screen.assets.down_right, graphics::DrawParam::default()
-                    .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+                    frame.meshes.push((self.screen.assets.down_right.clone(), self.screen.at(screen_i, screen_j)));
                 },
                 Orientation::Right => {
-                    graphics::draw(ctx, &self.screen.assets.left_down, graphics::DrawParam::default()
-                    .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+                    frame.meshes.push((self.screen.assets.left_down.clone(), self.screen.at(screen_i, screen_j)));
                 },
                 Orientation::Down => {
-                    graphics::draw(ctx, &self.screen.assets.up_left, graphics::DrawParam::default()
-                    .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+                    frame.meshes.push((self.screen.assets.up_left.clone(), self.screen.at(screen_i, screen_j)));
                 },
                 Orientation::Left => {
-                    graphics::draw(ctx, &self.screen.assets.right_up, graphics::DrawParam::default()
-                    .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+                    frame.meshes.push((self.screen.assets.right_up.clone(), self.screen.at(screen_i, screen_j)));
                 }
             }
         } else {
             // чёрный
             match self.ant.or {
                 Orientation::Up => {
-                    graphics::draw(ctx, &self.screen.assets.down_left, graphics::DrawParam::default()
-                    .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+                    frame.meshes.push((self.screen.assets.down_left.clone(), self.screen.at(screen_i, screen_j)));
                 },
                 Orientation::Right => {
-                    graphics::draw(ctx, &self.screen.assets.left_up, graphics::DrawParam::default()
-                    .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+                    frame.meshes.push((self.screen.assets.left_up.clone(), self.screen.at(screen_i, screen_j)));
                 },
                 Orientation::Down => {
-                    graphics::draw(ctx, &self.screen.assets.up_right, graphics::DrawParam::default()
-                    .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+                    frame.meshes.push((self.screen.assets.up_right.clone(), self.screen.at(screen_i, screen_j)));
                 },
                 Orientation::Left => {
-                    graphics::draw(ctx, &self.screen.assets.right_down, graphics::DrawParam::default()
-                    .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size)))?;
+                    frame.meshes.push((self.screen.assets.right_down.clone(), self.screen.at(screen_i, screen_j)));
                 }
             }
         }
+    }
 
-        Ok(())
+    fn draw_lod(&mut self) -> Frame {
+        let mut frame = self.screen.lod_frame(self.ant.coord);
+
+        let mut chunks = std::mem::take(&mut self.chunks);
+        chunks.frame(&mut frame, &self.screen.camera, |i, j| rgba8(self.tint((i, j))));
+        self.chunks = chunks;
+
+        frame
     }
 }
 
-impl Walker for AntWalker {
-    fn new(resolution: (f32, f32), dim: i64, ctx: &mut Context) -> Self {
-        let long_dim = (dim as f32 * (resolution.0 / resolution.1)) as i64;
-        let dim = (long_dim, dim);
-        let cell_size = resolution.0 / dim.0 as f32;
-        let center_coord = (0, 0);
-
+impl WalkerAssets for Assets {
+    fn new(cell_size: f32, theme: &Theme, ctx: &mut Context) -> Assets {
         let black_cell = ggez::graphics::MeshBuilder::new()
         .rectangle(
             DrawMode::fill(),
             graphics::Rect::new(0.0, 0.0, cell_size, cell_size),
-            BLACK.into(),
-        )
+            theme.ink.into(),
+        ).unwrap()
         .rectangle(
-            DrawMode::stroke(2.0),
+            DrawMode::stroke(theme.grid_thickness),
             graphics::Rect::new(0.0, 0.0, cell_size, cell_size),
-            PURPLE.into(),
-        )
+            theme.grid.into(),
+        ).unwrap()
         .build(ctx).unwrap();
 
         let white_cell = ggez::graphics::MeshBuilder::new()
         .rectangle(
             DrawMode::fill(),
             graphics::Rect::new(0.0, 0.0, cell_size, cell_size),
-            PAPER.into(),
-        )
+            theme.paper.into(),
+        ).unwrap()
         .rectangle(
-            DrawMode::stroke(2.0),
+            DrawMode::stroke(theme.grid_thickness),
             graphics::Rect::new(0.0, 0.0, cell_size, cell_size),
-            PURPLE.into(),
-        )
+            theme.grid.into(),
+        ).unwrap()
         .build(ctx).unwrap();
 
         let right_roads = ggez::graphics::MeshBuilder::new()
@@ -200,15 +354,15 @@ impl Walker for AntWalker {
             &[na::Point2::new(0.0, cell_size / 2.0),
               na::Point2::new(cell_size / 2.0, 0.0)
             ]
-            , 4.0
-            , BLUE.into()
+            , theme.road_thickness
+            , theme.roads.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size / 2.0, cell_size),
               na::Point2::new(cell_size, cell_size / 2.0)
             ]
-            , 4.0
-            , BLUE.into()
+            , theme.road_thickness
+            , theme.roads.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -217,15 +371,15 @@ impl Walker for AntWalker {
             &[na::Point2::new(cell_size / 2.0, 0.0),
               na::Point2::new(cell_size, cell_size / 2.0)
             ]
-            , 4.0
-            , BLUE.into()
+            , theme.road_thickness
+            , theme.roads.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, cell_size / 2.0),
               na::Point2::new(cell_size / 2.0, cell_size)
             ]
-            , 4.0
-            , BLUE.into()
+            , theme.road_thickness
+            , theme.roads.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -234,22 +388,22 @@ impl Walker for AntWalker {
             &[na::Point2::new(cell_size / 2.0, cell_size),
               na::Point2::new(0.0, cell_size / 2.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, cell_size * 0.5),
               na::Point2::new(cell_size * 0.25, cell_size * 0.5)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, cell_size * 0.5),
               na::Point2::new(0.0, cell_size * 0.75)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -258,22 +412,22 @@ impl Walker for AntWalker {
             &[na::Point2::new(cell_size / 2.0, cell_size),
               na::Point2::new(cell_size, cell_size / 2.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.75, cell_size / 2.0),
               na::Point2::new(cell_size, cell_size / 2.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size, cell_size * 0.75),
               na::Point2::new(cell_size, cell_size / 2.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -282,22 +436,22 @@ impl Walker for AntWalker {
             &[na::Point2::new(cell_size / 2.0, 0.0),
               na::Point2::new(cell_size, cell_size / 2.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size, cell_size * 0.5),
               na::Point2::new(cell_size, cell_size * 0.25)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size, cell_size * 0.5),
               na::Point2::new(cell_size * 0.75, cell_size * 0.5)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -306,22 +460,22 @@ impl Walker for AntWalker {
             &[na::Point2::new(cell_size / 2.0, 0.0),
               na::Point2::new(0.0, cell_size / 2.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, cell_size * 0.5),
               na::Point2::new(0.0, cell_size * 0.25)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, cell_size * 0.5),
               na::Point2::new(cell_size * 0.25, cell_size * 0.5)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -330,22 +484,22 @@ impl Walker for AntWalker {
             &[na::Point2::new(0.0, cell_size * 0.5),
               na::Point2::new(cell_size * 0.5, cell_size)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.5, cell_size),
               na::Point2::new(cell_size * 0.25, cell_size)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.5, cell_size),
               na::Point2::new(cell_size * 0.5, cell_size * 0.75)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -354,22 +508,22 @@ impl Walker for AntWalker {
             &[na::Point2::new(0.0, cell_size * 0.5),
               na::Point2::new(cell_size * 0.5, 0.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.5, 0.0),
               na::Point2::new(cell_size * 0.25, 0.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.5, 0.0),
               na::Point2::new(cell_size * 0.5, cell_size * 0.25)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -378,22 +532,22 @@ impl Walker for AntWalker {
             &[na::Point2::new(cell_size, cell_size * 0.5),
               na::Point2::new(cell_size * 0.5, cell_size)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.5, cell_size),
               na::Point2::new(cell_size * 0.75, cell_size)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.5, cell_size),
               na::Point2::new(cell_size * 0.5, cell_size * 0.75)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -402,84 +556,208 @@ impl Walker for AntWalker {
             &[na::Point2::new(cell_size, cell_size * 0.5),
               na::Point2::new(cell_size * 0.5, 0.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.5, 0.0),
               na::Point2::new(cell_size * 0.75, 0.0)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(cell_size * 0.5, 0.0),
               na::Point2::new(cell_size * 0.5, cell_size * 0.25)
             ]
-            , 4.0
-            , RED.into()
+            , theme.road_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
+        let marker = ggez::graphics::MeshBuilder::new()
+        .rectangle(
+            DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, cell_size.max(2.0), cell_size.max(2.0)),
+            theme.walker.into(),
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        // белая плитка, которую красим при рисовании
+        let tile = ggez::graphics::MeshBuilder::new()
+        .rectangle(
+            DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, cell_size, cell_size),
+            graphics::Color::WHITE,
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        Assets {
+            black_cell,
+            white_cell,
+            right_roads,
+            left_roads,
+            down_left,
+            down_right,
+            up_left,
+            up_right,
+            left_down,
+            left_up,
+            right_down,
+            right_up,
+            marker,
+            tile,
+        }
+    }
+
+    fn batched(&self) -> Vec<Mesh> {
+        vec!(self.tile.clone(), self.black_cell.clone(), self.white_cell.clone(), self.right_roads.clone(), self.left_roads.clone())
+    }
+
+    fn marker(&self) -> &Mesh {
+        &self.marker
+    }
+}
+
+impl Walker for AntWalker {
+    fn new(resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> Self {
         AntWalker {
-            screen: Screen {
-                assets: Assets {
-                    black_cell,
-                    white_cell,
-                    right_roads,
-                    left_roads,
-                    down_left,
-                    down_right,
-                    up_left,
-                    up_right,
-                    left_down,
-                    left_up,
-                    right_down,
-                    right_up,
-                }
-                , resolution
-                , dim
-                , cell_size
-                , center_coord
-            },
-            update: Update {
-                cells: Cells::All,
-                ant: true,
-            },
+            screen: Screen::new(resolution, dim, theme, ctx),
             board: HashSet::new(),
+            chunks: ChunkCache::new(),
+            explored: Bounds::new((0, 0)),
+            visits: Visits::new(),
+            changes: Changes::new(),
+            time: 0,
+            drawn_at: 0,
+            render: RenderMode::Board,
             ant: Ant {
                 coord: (0, 0)
                 , or: Orientation::Up
-            }
+            },
+            noise: None,
+            seed: 0,
+            sensing: None,
+            history: vec!(),
+            rule_map: None,
         }
     }
 
-    fn step(&mut self) {
-        let (board_i, board_j) = self.ant.coord;
-        let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        match &mut self.update.cells {
-            Cells::Some(ref mut v) => v.push((screen_i, screen_j)),
-            _ => {},
+    fn resize(&mut self, resolution: (f32, f32), ctx: &mut Context) {
+        self.screen.resize(resolution, ctx);
+    }
+
+    fn set_theme(&mut self, theme: &Theme, ctx: &mut Context) {
+        self.screen.set_theme(theme, ctx);
+        self.chunks.clear();
+    }
+
+    fn zoom(&mut self, factor: f32, around: (f32, f32), ctx: &mut Context) {
+        self.screen.zoom(factor, around, ctx);
+    }
+
+    fn pan(&mut self, dx: f32, dy: f32) {
+        self.screen.pan(dx, dy);
+    }
+
+    fn toggle_follow(&mut self) {
+        self.screen.toggle_follow(self.ant.coord);
+    }
+
+    fn toggle(&mut self, x: f32, y: f32) {
+        let (screen_i, screen_j) = self.screen.camera.pixel_to_screen(x, y);
+        let cell = self.screen_to_board(screen_i, screen_j);
+
+        if !self.board.remove(&cell) {
+            self.board.insert(cell);
         }
 
-        if self.board.contains(&(board_i, board_j)) {
-            match &self.ant.or {
-                Orientation::Up => self.ant.or = Orientation::Left,
-                Orientation::Left => self.ant.or = Orientation::Down,
-                Orientation::Down => self.ant.or = Orientation::Right,
-                Orientation::Right => self.ant.or = Orientation::Up,
-            }
+        self.chunks.invalidate(cell.0, cell.1);
+        if self.changes.record(cell, self.time) && self.render == RenderMode::Flips {
+            self.chunks.clear();
+            self.screen.redraw();
+        }
+        self.screen.mark(screen_i, screen_j);
+        self.screen.redraw_walker();
+    }
 
-            self.board.remove(&(board_i, board_j));
+    fn place(&mut self, x: f32, y: f32) {
+        let (screen_i, screen_j) = self.screen.camera.pixel_to_screen(x, y);
+        let cell = self.screen_to_board(screen_i, screen_j);
+
+        if cell == self.ant.coord {
+            self.ant.or = match self.ant.or {
+                Orientation::Up => Orientation::Right,
+                Orientation::Right => Orientation::Down,
+                Orientation::Down => Orientation::Left,
+                Orientation::Left => Orientation::Up,
+            };
         } else {
-            match &self.ant.or {
-                Orientation::Up => self.ant.or = Orientation::Right,
-                Orientation::Left => self.ant.or = Orientation::Up,
-                Orientation::Down => self.ant.or = Orientation::Left,
-                Orientation::Right => self.ant.or = Orientation::Down,
-            }
+            let (old_i, old_j) = self.board_to_screen(self.ant.coord.0, self.ant.coord.1);
+            self.screen.mark(old_i, old_j);
+            self.ant.coord = cell;
+            self.explored.extend(cell);
+        }
+
+        self.screen.mark(screen_i, screen_j);
+        self.screen.redraw_walker();
+    }
 
-            self.board.insert((board_i, board_j));
+    fn redraw(&mut self) {
+        self.screen.redraw();
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            coord: self.ant.coord,
+            heading: match self.ant.or {
+                Orientation::Up => "up",
+                Orientation::Down => "down",
+                Orientation::Left => "left",
+                Orientation::Right => "right",
+            },
+            population: self.board.len(),
+            population_label: "black cells",
+            explored: self.explored,
+        }
+    }
+
+    fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render = mode;
+        self.chunks.clear();
+        self.redraw();
+    }
+
+    fn step(&mut self) -> bool {
+        let (board_i, board_j) = self.ant.coord;
+        self.screen.mark_cell((board_i, board_j));
+
+        let black = self.board.contains(&(board_i, board_j));
+        let (quarters, flip) = match &self.sensing {
+            Some(sensing) => {
+                let decision = self.mapped(black).unwrap_or_else(|| sensing.table[self.sense(sensing.neighbourhood)]);
+                if self.history.len() == HISTORY {
+                    self.history.drain(..HISTORY / 2);
+                }
+                self.history.push(((board_i, board_j), self.ant.or, Some(black).filter(|_| decision.1)));
+                decision
+            },
+            None => self.decide(black, self.time),
+        };
+        self.turn(quarters);
+
+        self.chunks.invalidate(board_i, board_j);
+        self.time += 1;
+        if flip {
+            if black {
+                self.board.remove(&(board_i, board_j));
+            } else {
+                self.board.insert((board_i, board_j));
+            }
+            if self.changes.record((board_i, board_j), self.time) && self.render == RenderMode::Flips {
+                self.chunks.clear();
+                self.screen.redraw();
+            }
         }
 
         // шагаем
@@ -490,130 +768,144 @@ impl Walker for AntWalker {
             Orientation::Right => self.ant.coord.0 += 1,
         }
 
-        let (new_screen_i, new_screen_j) = self.board_to_screen(self.ant.coord.0, self.ant.coord.1);
-        if new_screen_i < 0 {
-            self.screen.center_coord.0 -= 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_i > self.screen.dim.0 {
-            self.screen.center_coord.0 += 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_j < 0 {
-            self.screen.center_coord.1 -= 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_j > self.screen.dim.1 {
-            self.screen.center_coord.1 += 1;
-            self.update.cells = Cells::All;
-        }
+        self.screen.track(self.ant.coord);
+        self.screen.mark_cell(self.ant.coord);
 
-        let (board_i, board_j) = self.ant.coord;
-        let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        match &mut self.update.cells {
-            Cells::Some(ref mut v) => v.push((screen_i, screen_j)),
-            _ => {},
+        self.explored.extend(self.ant.coord);
+        self.chunks.invalidate(self.ant.coord.0, self.ant.coord.1);
+        if self.visits.visit(self.ant.coord) && self.render == RenderMode::Visits {
+            self.chunks.clear();
+            self.screen.redraw();
         }
-
-        self.update.ant = true;
+        self.screen.limit_dirty();
+        true
     }
 
-    fn step_back(&mut self) {
+    fn step_back(&mut self) -> bool {
+        let sensed = match self.sensing {
+            Some(_) => match self.history.pop() {
+                Some(entry) => Some(entry),
+                None => return false,
+            },
+            None => None,
+        };
+
+        self.visits.unvisit(self.ant.coord);
+        self.chunks.invalidate(self.ant.coord.0, self.ant.coord.1);
+
         let (board_i, board_j) = self.ant.coord;
         let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        match &mut self.update.cells {
-            Cells::Some(ref mut v) => {
-                v.push((screen_i, screen_j));
-                v.push((screen_i - 1, screen_j));
-                v.push((screen_i + 1, screen_j));
-                v.push((screen_i, screen_j - 1));
-                v.push((screen_i, screen_j + 1));
+        self.screen.mark(screen_i, screen_j);
+        self.screen.mark(screen_i - 1, screen_j);
+        self.screen.mark(screen_i + 1, screen_j);
+        self.screen.mark(screen_i, screen_j - 1);
+        self.screen.mark(screen_i, screen_j + 1);
+
+        self.time = self.time.saturating_sub(1);
+        let (cell, was_black) = match sensed {
+            Some((cell, or, was_black)) => {
+                self.ant.coord = cell;
+                self.ant.or = or;
+                (cell, was_black)
             },
-            _ => {},
-        }
+            None => {
+                // шагаем
+                match &self.ant.or {
+                    Orientation::Up => self.ant.coord.1 += 1,
+                    Orientation::Left => self.ant.coord.0 += 1,
+                    Orientation::Down => self.ant.coord.1 -= 1,
+                    Orientation::Right => self.ant.coord.0 -= 1,
+                }
 
-        // шагаем
-        match &self.ant.or {
-            Orientation::Up => self.ant.coord.1 += 1,
-            Orientation::Left => self.ant.coord.0 += 1,
-            Orientation::Down => self.ant.coord.1 -= 1,
-            Orientation::Right => self.ant.coord.0 -= 1,
+                // каким был цвет до шага, видно по тому, перекрашивал ли он клетку
+                let cell = self.ant.coord;
+                let (_, flip) = self.decide(false, self.time);
+                let black = self.board.contains(&cell) != flip;
+                self.turn(4 - self.decide(black, self.time).0);
+                (cell, Some(black).filter(|_| flip))
+            },
+        };
+
+        let (board_i, board_j) = cell;
+        if let Some(black) = was_black {
+            if black {
+                self.board.insert((board_i, board_j));
+            } else {
+                self.board.remove(&(board_i, board_j));
+            }
+            self.changes.undo((board_i, board_j), self.time);
         }
+        self.chunks.invalidate(board_i, board_j);
 
-        let (board_i, board_j) = self.ant.coord;
-        if self.board.contains(&(board_i, board_j)) {
-            match &self.ant.or {
-                Orientation::Up => self.ant.or = Orientation::Left,
-                Orientation::Left => self.ant.or = Orientation::Down,
-                Orientation::Down => self.ant.or = Orientation::Right,
-                Orientation::Right => self.ant.or = Orientation::Up,
-            }
+        self.screen.track(self.ant.coord);
+        self.screen.mark_cell(self.ant.coord);
 
-            self.board.remove(&(board_i, board_j));
-        } else {
-            match &self.ant.or {
-                Orientation::Up => self.ant.or = Orientation::Right,
-                Orientation::Left => self.ant.or = Orientation::Up,
-                Orientation::Down => self.ant.or = Orientation::Left,
-                Orientation::Right => self.ant.or = Orientation::Down,
-            }
+        self.explored.extend(self.ant.coord);
+        self.screen.limit_dirty();
+        true
+    }
 
-            self.board.insert((board_i, board_j));
-        }
+    // обычный и шумный муравей откатываются по доске сколько угодно
+    fn can_step_back(&self) -> bool {
+        self.sensing.is_none() || !self.history.is_empty()
+    }
 
-        let (new_screen_i, new_screen_j) = self.board_to_screen(self.ant.coord.0, self.ant.coord.1);
-        if new_screen_i < 0 {
-            self.screen.center_coord.0 -= 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_i > self.screen.dim.0 {
-            self.screen.center_coord.0 += 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_j < 0 {
-            self.screen.center_coord.1 -= 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_j > self.screen.dim.1 {
-            self.screen.center_coord.1 += 1;
-            self.update.cells = Cells::All;
-        }
+    fn set_up(&mut self, setup: &Setup) {
+        self.seed = setup.seed;
+        self.board = setup.layout(2).cells.into_iter().map(|(cell, _)| cell).collect();
+        self.ant.coord = setup.start;
+        self.ant.or = match setup.heading.rem_euclid(4) {
+            0 => Orientation::Up,
+            1 => Orientation::Right,
+            2 => Orientation::Down,
+            _ => Orientation::Left,
+        };
+        self.explored = Bounds::new(setup.start);
+        self.screen.camera.center_coord = setup.start;
+        self.chunks.clear();
+        self.redraw();
+    }
 
-        let (board_i, board_j) = self.ant.coord;
-        let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        match &mut self.update.cells {
-            Cells::Some(ref mut v) => v.push((screen_i, screen_j)),
-            _ => {},
-        }
+    // у муравья два цвета, так что правила в карте из двух букв
+    fn set_rule_map(&mut self, map: &RuleMap) -> Result<(), String> {
+        map.check_letters("ant", 2)?;
+        self.rule_map = Some(map.clone());
+        Ok(())
+    }
 
-        self.update.ant = true;
+    fn cells(&self) -> Option<Vec<((i64, i64), i64)>> {
+        Some(self.board.iter().map(|&cell| (cell, 1)).collect())
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        match self.update.cells {
-            Cells::Some(ref cells) => {
-                for &(i, j) in cells {
-                    self.draw_cell(ctx, i, j)?;
-                }
+    fn symmetries(&self) -> Vec<Symmetry> {
+        let board = &self.board;
+        find_symmetries(board.iter().cloned(), self.ant.coord, |cell| Some(1).filter(|_| board.contains(&cell)))
+    }
 
-                self.update.cells = Cells::Some(vec!());
-            },
-            Cells::All => {
-                for i in 0..self.screen.dim.0 {
-                    for j in 0..self.screen.dim.1 {
-                        self.draw_cell(ctx, i, j)?;
-                    }
-                }
-                self.update.cells = Cells::Some(vec!());
-            },
+    fn frame(&mut self) -> Frame {
+        // при раскраске по давности с каждым шагом меняется вся доска
+        if self.render == RenderMode::Recency && self.drawn_at != self.time {
+            self.chunks.clear();
+            self.screen.redraw();
         }
+        self.drawn_at = self.time;
 
-        if self.update.ant {
-            self.draw_ant(ctx)?;
-            self.update.ant = false;
+        if self.screen.lod() {
+            return self.draw_lod();
         }
 
-        graphics::present(ctx)?;
-        Ok(())
+        let mut frame = Frame::new();
+        for (i, j) in self.screen.dirty(&mut frame) {
+            self.draw_cell(i, j);
+        }
+        if self.screen.finish(&mut frame) {
+            self.draw_ant(&mut frame);
+        }
+
+        frame
+    }
+
+    fn recycle(&mut self, batches: Vec<MeshBatch>) {
+        self.screen.recycle(batches);
     }
 }
@@ -0,0 +1,94 @@
+// Строчка из файла настроек, разбитая на слова. Числа достаются по номеру слова,
+// а ошибки у всех файлов выходят одинаковые.
+pub struct Words<'a> {
+    pub line: &'a str,
+    words: Vec<&'a str>,
+}
+
+impl<'a> std::ops::Deref for Words<'a> {
+    type Target = [&'a str];
+
+    fn deref(&self) -> &[&'a str] {
+        &self.words
+    }
+}
+
+impl<'a> Words<'a> {
+    pub fn number<T: std::str::FromStr>(&self, k: usize) -> Result<T, String> {
+        self.words.get(k).ok_or(format!("`{}` needs more arguments", self.line))?
+        .parse().map_err(|_| format!("bad number in `{}`", self.line))
+    }
+
+    pub fn int(&self, k: usize) -> Result<i64, String> {
+        self.number(k)
+    }
+
+    pub fn float(&self, k: usize) -> Result<f64, String> {
+        self.number(k)
+    }
+
+    // все слова сразу, для списков чисел вроде цвета
+    pub fn numbers<T: std::str::FromStr>(&self) -> Result<Vec<T>, String> {
+        (0..self.words.len()).map(|k| self.number(k)).collect()
+    }
+}
+
+// Строчки файла настроек с номерами от единицы: строчки — это и переводы строк,
+// и точки с запятой, после # комментарий, пустые строчки пропускаются.
+pub fn config_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(['\n', ';']).enumerate()
+    .map(|(n, line)| (n + 1, line.split('#').next().unwrap().trim()))
+    .filter(|(_, line)| !line.is_empty())
+}
+
+// Строчка вида `key = value`: ключ и слова значения.
+pub fn key_value(line: &str) -> Result<(&str, Words<'_>), String> {
+    let (key, value) = line.split_once('=').ok_or_else(|| format!("expected `key = value`, got `{}`", line))?;
+    Ok((key.trim(), Words {
+        line,
+        words: value.split_whitespace().collect(),
+    }))
+}
+
+// Разбирает файл настроек целиком, ошибка начинается с номера строчки.
+pub fn parse_lines<'a, T, F>(text: &'a str, mut parse: F) -> Result<Vec<T>, String>
+where F: FnMut(&Words<'a>) -> Result<T, String> {
+    let mut parsed = vec!();
+
+    for (n, line) in config_lines(text) {
+        let words = Words {
+            line,
+            words: line.split_whitespace().collect(),
+        };
+        parsed.push(parse(&words).map_err(|e| format!("{}: {}", n, e))?);
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_split_on_semicolons_and_skip_comments() {
+        let lines = parse_lines("a 1 # x\n\n  # y\nb 2; c 3 ;;", |words| Ok((words[0].to_string(), words.int(1)?))).unwrap();
+        assert_eq!(lines, vec!((String::from("a"), 1), (String::from("b"), 2), (String::from("c"), 3)));
+
+        let error = parse_lines("a 1\n# x\nb 2.5", |words| words.int(1)).err().unwrap();
+        assert_eq!(error, "3: bad number in `b 2.5`");
+        let error = parse_lines("a", |words| words.float(1)).err().unwrap();
+        assert_eq!(error, "1: `a` needs more arguments");
+    }
+
+    #[test]
+    fn key_value_splits_on_the_first_equals() {
+        let (key, value) = key_value("paper = 0.1 0.2 0.3").unwrap();
+        assert_eq!(key, "paper");
+        assert_eq!(value.numbers::<f32>().unwrap(), vec!(0.1, 0.2, 0.3));
+
+        let (_, value) = key_value("ink = 1 x").unwrap();
+        assert_eq!(value.numbers::<f32>().err().unwrap(), "bad number in `ink = 1 x`");
+        assert!(key_value("paper 0.1").is_err());
+    }
+}
@@ -1,20 +1,11 @@
 use ggez::*;
 use std::collections::HashSet;
-use ggez::{nalgebra as na};
-use ggez::graphics::{self, DrawMode, Mesh};
-use ggez::event::{EventHandler, KeyCode, KeyMods};
-use ggez::input::keyboard;
+use nalgebra as na;
+use ggez::graphics::{self, DrawMode, Mesh, MeshBatch};
 
 use crate::lib::*;
 
-const PURPLE: [f32; 4] = [0.4, 0.0, 0.2, 1.0];
-const PAPER: [f32; 4] = [0.8, 0.8, 0.6, 1.0];
-const RED: [f32; 4] = [0.8, 0.0, 0.0, 1.0];
-const BLUE: [f32; 4] = [0.2, 0.0, 0.5, 1.0];
-const BLACK: [f32; 4] = [0.05, 0.0, 0.0, 1.0];
-
-const LINE_THICKNESS: f32 = 6.0;
-
+#[derive(Clone, Copy)]
 pub enum Orientation {
     Up,
     Down,
@@ -22,6 +13,9 @@ pub enum Orientation {
     Right,
 }
 
+// сколько шагов назад может откатить инженер
+const HISTORY: usize = 1 << 20;
+
 pub struct Engineer {
     coord: (i64, i64),
     or: Orientation
@@ -41,168 +35,289 @@ struct Assets {
     right_blank: Mesh,
     up_blank: Mesh,
     down_blank: Mesh,
-    screen_blank: Mesh,
+    marker: Mesh,
+    tile: Mesh,
+    h_white: Mesh,
+    v_white: Mesh,
 }
 
-pub struct Screen {
-    assets: Assets,
-    resolution: (f32, f32),
-    dim: (i64, i64),
-    bridge_len: f32,
-    center_coord: (i64, i64),
-}
+// узлы копятся в батчах и рисуются одним вызовом на каждый меш:
+// сначала всё стираем, потом рисуем узлы и мосты
+const TILE: usize = 0;
+const UP_BLANK: usize = 1;
+const LEFT_BLANK: usize = 2;
+const DOWN_BLANK: usize = 3;
+const RIGHT_BLANK: usize = 4;
+const H_BLANK: usize = 5;
+const V_BLANK: usize = 6;
+const NODE: usize = 7;
+const H_LINE: usize = 8;
+const V_LINE: usize = 9;
+const H_TINT: usize = 10;
+const V_TINT: usize = 11;
 
-enum Nodes {
-    Some(Vec<(i64, i64)>),
-    All,
+pub struct EngineerWalker {
+    screen: Screen<Assets>,
+    bridges: HashSet<(i64, i64, i64, i64)>,
+    chunks: ChunkCache,
+    explored: Bounds,
+    visits: Visits,
+    changes: Changes<(i64, i64, i64, i64)>,
+    // сколько шагов сделано, для раскраски по давности
+    time: u64,
+    // на каком шаге доска была нарисована в последний раз
+    drawn_at: u64,
+    render: RenderMode,
+    engineer: Engineer,
+    // в областях карты повороты после постройки и перехода берутся из их правил
+    rule_map: Option<RuleMap>,
+    // откуда и куда смотрел инженер и перешёл ли он мост, для шага назад
+    history: Vec<((i64, i64), Orientation, bool)>,
 }
 
-pub struct Update {
-    nodes: Nodes,
-    engineer: bool,
+// соседний узел в ту сторону, куда смотрит инженер
+fn ahead((i, j): (i64, i64), or: Orientation) -> (i64, i64) {
+    match or {
+        Orientation::Up => (i, j - 1),
+        Orientation::Left => (i - 1, j),
+        Orientation::Down => (i, j + 1),
+        Orientation::Right => (i + 1, j),
+    }
 }
 
-pub struct EngineerWalker {
-    screen: Screen,
-    update: Update,
-    bridges: HashSet<(i64, i64, i64, i64)>,
-    engineer: Engineer,
+// мост без направления, чтобы история не зависела от порядка концов
+fn edge(a: (i64, i64), b: (i64, i64)) -> (i64, i64, i64, i64) {
+    if a <= b {
+        (a.0, a.1, b.0, b.1)
+    } else {
+        (b.0, b.1, a.0, a.1)
+    }
 }
 
 impl EngineerWalker {
+    // на сколько четвертей по часовой повернуть в узле после постройки моста и после перехода;
+    // сам инженер строит и поворачивает направо, а перейдя, поворачивает налево
+    fn turns(&self, node: (i64, i64)) -> (u8, u8) {
+        match self.rule_map.as_ref().and_then(|map| map.rule_at(node)) {
+            Some(rule) => {
+                let mut letters = rule.chars();
+                (quarters(letters.next().unwrap()), quarters(letters.next().unwrap()))
+            },
+            None => (1, 3),
+        }
+    }
+
+    fn turn(&mut self, quarters: u8) {
+        for _ in 0..quarters {
+            self.engineer.or = match self.engineer.or {
+                Orientation::Up => Orientation::Right,
+                Orientation::Right => Orientation::Down,
+                Orientation::Down => Orientation::Left,
+                Orientation::Left => Orientation::Up,
+            };
+        }
+    }
+
     fn board_to_screen(&self, board_i: i64, board_j: i64) -> (i64, i64) {
-        // кординаты центра доски с точки зрения верхнего левого угла
-        let (cx, cy) = ((self.screen.dim.0 / 2) as i64, (self.screen.dim.1 / 2) as i64);
-        // координаты центра с точки зрения (0, 0) доски
-        let (cent_x, cent_y) = self.screen.center_coord;
-        (board_i + cx - cent_x, board_j + cy - cent_y)
+        self.screen.camera.board_to_screen(board_i, board_j)
     }
 
     fn screen_to_board(&self, screen_i: i64, screen_j: i64) -> (i64, i64) {
-        // кординаты центра доски с точки зрения верхнего левого угла
-        let (cx, cy) = ((self.screen.dim.0 / 2) as i64, (self.screen.dim.1 / 2) as i64);
-        // координаты центра с точки зрения (0, 0) доски
-        let (cent_x, cent_y) = self.screen.center_coord;
-        (screen_i - cx + cent_x, screen_j - cy + cent_y)
+        self.screen.camera.screen_to_board(screen_i, screen_j)
     }
 
-    fn draw_node(&self, ctx: &mut Context, screen_i: i64, screen_j: i64) -> GameResult {
+    fn draw_node(&mut self, screen_i: i64, screen_j: i64) {
         let (board_i, board_j) = self.screen_to_board(screen_i, screen_j);
-        let bridge_len = self.screen.bridge_len;
-
-        // чистим стрелочку
-        graphics::draw(ctx, &self.screen.assets.up_blank, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.left_blank, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.down_blank, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.right_blank, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        // чистим 4 моста
-        graphics::draw(ctx, &self.screen.assets.h_blank, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.v_blank, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.h_blank, graphics::DrawParam::default()
-        .dest(na::Point2::new((screen_i-1) as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.v_blank, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, (screen_j-1) as f32 * bridge_len)))?;
+        let tint = match self.render {
+            RenderMode::Board => None,
+            RenderMode::Visits => Some(self.tint((board_i, board_j))),
+            // узел остаётся фоном, красятся сами мосты
+            RenderMode::Recency | RenderMode::Flips => Some(self.screen.theme.paper),
+        };
+        let colors = [
+            self.bridge_tint((board_i, board_j), (board_i-1, board_j)),
+            self.bridge_tint((board_i, board_j), (board_i, board_j-1)),
+            self.bridge_tint((board_i, board_j), (board_i+1, board_j)),
+            self.bridge_tint((board_i, board_j), (board_i, board_j+1)),
+        ];
+        let bridge_len = self.screen.camera.cell_size;
+        let at = |i: i64, j: i64| graphics::DrawParam::default()
+        .dest(na::Point2::new(i as f32 * bridge_len, j as f32 * bridge_len));
+        let batches = &mut self.screen.batches;
+
+        if let Some(color) = tint {
+            // плитка закрывает и стрелочку, и половинки мостов вокруг узла
+            batches[TILE].add(at(screen_i, screen_j).color(color.into()));
+        } else {
+            // чистим стрелочку
+            batches[UP_BLANK].add(at(screen_i, screen_j));
+            batches[LEFT_BLANK].add(at(screen_i, screen_j));
+            batches[DOWN_BLANK].add(at(screen_i, screen_j));
+            batches[RIGHT_BLANK].add(at(screen_i, screen_j));
+
+            // чистим 4 моста
+            batches[H_BLANK].add(at(screen_i, screen_j));
+            batches[V_BLANK].add(at(screen_i, screen_j));
+            batches[H_BLANK].add(at(screen_i-1, screen_j));
+            batches[V_BLANK].add(at(screen_i, screen_j-1));
+        }
 
         // перерисовываем все ноды
-        graphics::draw(ctx, &self.screen.assets.node, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.node, graphics::DrawParam::default()
-        .dest(na::Point2::new((screen_i + 1) as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.node, graphics::DrawParam::default()
-        .dest(na::Point2::new((screen_i-1) as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.node, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, (screen_j-1) as f32 * bridge_len)))?;
-
-        graphics::draw(ctx, &self.screen.assets.node, graphics::DrawParam::default()
-        .dest(na::Point2::new(screen_i as f32 * bridge_len, (screen_j+1) as f32 * bridge_len)))?;
+        batches[NODE].add(at(screen_i, screen_j));
+        batches[NODE].add(at(screen_i + 1, screen_j));
+        batches[NODE].add(at(screen_i-1, screen_j));
+        batches[NODE].add(at(screen_i, screen_j-1));
+        batches[NODE].add(at(screen_i, screen_j+1));
 
         // перерисовываем мосты
         if self.bridges.contains(&(board_i-1, board_j, board_i, board_j))
         || self.bridges.contains(&(board_i, board_j, board_i-1, board_j)) {
-            graphics::draw(ctx, &self.screen.assets.h_line, graphics::DrawParam::default()
-            .dest(na::Point2::new((screen_i-1) as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
+            match colors[0] {
+                Some(color) => batches[H_TINT].add(at(screen_i-1, screen_j).color(color.into())),
+                None => batches[H_LINE].add(at(screen_i-1, screen_j)),
+            };
         }
 
         if self.bridges.contains(&(board_i, board_j-1, board_i, board_j))
         || self.bridges.contains(&(board_i, board_j, board_i, board_j-1)) {
-            graphics::draw(ctx, &self.screen.assets.v_line, graphics::DrawParam::default()
-            .dest(na::Point2::new(screen_i as f32 * bridge_len, (screen_j-1) as f32 * bridge_len)))?;
+            match colors[1] {
+                Some(color) => batches[V_TINT].add(at(screen_i, screen_j-1).color(color.into())),
+                None => batches[V_LINE].add(at(screen_i, screen_j-1)),
+            };
         }
 
         if self.bridges.contains(&(board_i, board_j, board_i+1, board_j))
         || self.bridges.contains(&(board_i+1, board_j, board_i, board_j)) {
-            graphics::draw(ctx, &self.screen.assets.h_line, graphics::DrawParam::default()
-            .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
+            match colors[2] {
+                Some(color) => batches[H_TINT].add(at(screen_i, screen_j).color(color.into())),
+                None => batches[H_LINE].add(at(screen_i, screen_j)),
+            };
         }
 
         if self.bridges.contains(&(board_i, board_j, board_i, board_j+1))
         || self.bridges.contains(&(board_i, board_j+1, board_i, board_j)) {
-            graphics::draw(ctx, &self.screen.assets.v_line, graphics::DrawParam::default()
-            .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
+            match colors[3] {
+                Some(color) => batches[V_TINT].add(at(screen_i, screen_j).color(color.into())),
+                None => batches[V_LINE].add(at(screen_i, screen_j)),
+            };
         }
-
-        Ok(())
     }
 
-    fn draw_engineer(&self, ctx: &mut Context) -> GameResult {
+    fn draw_engineer(&self, frame: &mut Frame) {
         let (board_i, board_j) = self.engineer.coord;
         let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        let bridge_len = self.screen.bridge_len;
 
         match self.engineer.or {
             Orientation::Up => {
-                graphics::draw(ctx, &self.screen.assets.up, graphics::DrawParam::default()
-                .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
+                frame.meshes.push((self.screen.assets.up.clone(), self.screen.at(screen_i, screen_j)));
             },
             Orientation::Right => {
-                graphics::draw(ctx, &self.screen.assets.right, graphics::DrawParam::default()
-                .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
+                frame.meshes.push((self.screen.assets.right.clone(), self.screen.at(screen_i, screen_j)));
             },
             Orientation::Down => {
-                graphics::draw(ctx, &self.screen.assets.down, graphics::DrawParam::default()
-                .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
+                frame.meshes.push((self.screen.assets.down.clone(), self.screen.at(screen_i, screen_j)));
             },
             Orientation::Left => {
-                graphics::draw(ctx, &self.screen.assets.left, graphics::DrawParam::default()
-                .dest(na::Point2::new(screen_i as f32 * bridge_len, screen_j as f32 * bridge_len)))?;
+                frame.meshes.push((self.screen.assets.left.clone(), self.screen.at(screen_i, screen_j)));
             }
         }
+    }
 
-        Ok(())
+    fn bridge_count(&self, board_i: i64, board_j: i64) -> usize {
+        [(board_i - 1, board_j), (board_i + 1, board_j), (board_i, board_j - 1), (board_i, board_j + 1)]
+        .iter()
+        .filter(|&&(i, j)| self.bridges.contains(&(board_i, board_j, i, j))
+            || self.bridges.contains(&(i, j, board_i, board_j)))
+        .count()
     }
-}
 
-impl Walker for EngineerWalker {
-    fn new(resolution: (f32, f32), dim: i64, ctx: &mut Context) -> Self {
-        let long_dim = (dim as f32 * (resolution.0 / resolution.1)) as i64;
-        let dim = (long_dim, dim);
-        let bridge_len = resolution.0 / dim.0 as f32; // !
-        let center_coord = (0, 0);
+    // узел и все четыре моста вокруг него
+    fn mark_around(&mut self, screen_i: i64, screen_j: i64) {
+        self.screen.mark(screen_i, screen_j);
+        self.screen.mark(screen_i - 1, screen_j);
+        self.screen.mark(screen_i + 1, screen_j);
+        self.screen.mark(screen_i, screen_j - 1);
+        self.screen.mark(screen_i, screen_j + 1);
+    }
+
+    // цвет узла в мелком масштабе и в режимах раскраски
+    fn tint(&self, node: (i64, i64)) -> [f32; 4] {
+        let theme = &self.screen.theme;
+        match self.render {
+            // чем больше мостов у узла, тем он темнее
+            RenderMode::Board => {
+                let t = self.bridge_count(node.0, node.1) as f32 / 4.0;
+                let mut color = [0.0; 4];
+                for (k, c) in color.iter_mut().enumerate() {
+                    *c = theme.paper[k] * (1.0 - t) + theme.ink[k] * t;
+                }
+                color
+            },
+            RenderMode::Visits => match self.visits.get(node) {
+                0 => theme.paper,
+                n => heat_color(n, self.visits.max),
+            },
+            // в мелком масштабе узел берёт цвет самого свежего или самого частого из своих мостов
+            RenderMode::Recency => match self.node_changes(node).iter().map(|c| c.0).max() {
+                Some(changed) => recency_color(changed, self.time, theme.walker, theme.paper),
+                None => theme.paper,
+            },
+            RenderMode::Flips => match self.node_changes(node).iter().map(|c| c.1).max() {
+                Some(flips) if flips > 0 => heat_color(flips, self.changes.max_flips),
+                _ => theme.paper,
+            },
+        }
+    }
+
+    fn node_changes(&self, node: (i64, i64)) -> Vec<(u64, u64)> {
+        let (i, j) = node;
+        [(i - 1, j), (i + 1, j), (i, j - 1), (i, j + 1)]
+        .iter()
+        .filter_map(|&other| self.changes.get(&edge(node, other)))
+        .collect()
+    }
+
+    // цвет моста в режимах давности, None — обычные чернила
+    fn bridge_tint(&self, a: (i64, i64), b: (i64, i64)) -> Option<[f32; 4]> {
+        let theme = &self.screen.theme;
+        match (self.render, self.changes.get(&edge(a, b))) {
+            (RenderMode::Recency, Some((changed, _))) => Some(recency_color(changed, self.time, theme.walker, theme.ink)),
+            (RenderMode::Flips, Some((_, flips))) if flips > 0 => Some(heat_color(flips, self.changes.max_flips)),
+            (RenderMode::Recency, _) | (RenderMode::Flips, _) => Some(theme.ink),
+            _ => None,
+        }
+    }
+
+    // запоминаем изменение моста, при росте шкалы переключений перекрашиваем всё
+    fn record(&mut self, a: (i64, i64), b: (i64, i64)) {
+        if self.changes.record(edge(a, b), self.time) && self.render == RenderMode::Flips {
+            self.chunks.clear();
+            self.screen.redraw();
+        }
+    }
+
+    fn draw_lod(&mut self) -> Frame {
+        let mut frame = self.screen.lod_frame(self.engineer.coord);
+
+        // пиксель на узел
+        let mut chunks = std::mem::take(&mut self.chunks);
+        chunks.frame(&mut frame, &self.screen.camera, |i, j| rgba8(self.tint((i, j))));
+        self.chunks = chunks;
+
+        frame
+    }
+}
 
+impl WalkerAssets for Assets {
+    fn new(bridge_len: f32, theme: &Theme, ctx: &mut Context) -> Assets {
         let node = ggez::graphics::MeshBuilder::new()
         .circle(
             DrawMode::fill(),
             na::Point2::new(0.0, 0.0),
-            8.0,
+            theme.node_radius,
             4.,
-            BLACK.into()
-        )
+            theme.ink.into()
+        ).unwrap()
         .build(ctx).unwrap();
 
         let h_line = ggez::graphics::MeshBuilder::new()
@@ -210,8 +325,8 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(bridge_len, 0.0)
             ]
-            , LINE_THICKNESS
-            , BLACK.into()
+            , theme.bridge_thickness
+            , theme.ink.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -220,8 +335,8 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(0.0, bridge_len)
             ]
-            , LINE_THICKNESS
-            , BLACK.into()
+            , theme.bridge_thickness
+            , theme.ink.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -230,22 +345,22 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(0.0, -bridge_len * 0.5)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, -bridge_len * 0.5),
               na::Point2::new(-bridge_len * 0.25, -bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, -bridge_len * 0.5),
               na::Point2::new(bridge_len * 0.25, -bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -254,22 +369,22 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(0.0, bridge_len * 0.5)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, bridge_len * 0.5),
               na::Point2::new(-bridge_len * 0.25, bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, bridge_len * 0.5),
               na::Point2::new(bridge_len * 0.25, bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -278,22 +393,22 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(-bridge_len * 0.5, 0.0)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(-bridge_len * 0.5, 0.0),
               na::Point2::new(-bridge_len * 0.25, bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(-bridge_len * 0.5, 0.0),
               na::Point2::new(-bridge_len * 0.25, -bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -302,22 +417,22 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(bridge_len * 0.5, 0.0)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(bridge_len * 0.5, 0.0),
               na::Point2::new(bridge_len * 0.25, bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .line(
             &[na::Point2::new(bridge_len * 0.5, 0.0),
               na::Point2::new(bridge_len * 0.25, -bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , RED.into()
+            , theme.bridge_thickness
+            , theme.walker.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -326,8 +441,8 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(bridge_len, 0.0)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -336,8 +451,8 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(0.0, bridge_len)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -346,22 +461,22 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(0.0, -bridge_len * 0.5)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, -bridge_len * 0.5),
               na::Point2::new(-bridge_len * 0.25, -bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, -bridge_len * 0.5),
               na::Point2::new(bridge_len * 0.25, -bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -370,22 +485,22 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(0.0, bridge_len * 0.5)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, bridge_len * 0.5),
               na::Point2::new(-bridge_len * 0.25, bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .line(
             &[na::Point2::new(0.0, bridge_len * 0.5),
               na::Point2::new(bridge_len * 0.25, bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -394,22 +509,22 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(-bridge_len * 0.5, 0.0)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .line(
             &[na::Point2::new(-bridge_len * 0.5, 0.0),
               na::Point2::new(-bridge_len * 0.25, bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .line(
             &[na::Point2::new(-bridge_len * 0.5, 0.0),
               na::Point2::new(-bridge_len * 0.25, -bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .build(ctx).unwrap();
 
@@ -418,246 +533,371 @@ impl Walker for EngineerWalker {
             &[na::Point2::new(0.0, 0.0),
               na::Point2::new(bridge_len * 0.5, 0.0)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .line(
             &[na::Point2::new(bridge_len * 0.5, 0.0),
               na::Point2::new(bridge_len * 0.25, bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .line(
             &[na::Point2::new(bridge_len * 0.5, 0.0),
               na::Point2::new(bridge_len * 0.25, -bridge_len * 0.25)
             ]
-            , LINE_THICKNESS
-            , PAPER.into()
+            , theme.bridge_thickness
+            , theme.paper.into()
         ).unwrap()
         .build(ctx).unwrap();
 
-        let screen_blank = ggez::graphics::MeshBuilder::new()
+        let marker = ggez::graphics::MeshBuilder::new()
         .rectangle(
             DrawMode::fill(),
-            graphics::Rect::new(0.0, 0.0, resolution.0, resolution.1),
-            PAPER.into(),
-        )
+            graphics::Rect::new(-1.0, -1.0, bridge_len.max(2.0), bridge_len.max(2.0)),
+            theme.walker.into(),
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        // белая плитка вокруг узла, которую красим при рисовании
+        let tile = ggez::graphics::MeshBuilder::new()
+        .rectangle(
+            DrawMode::fill(),
+            graphics::Rect::new(-bridge_len * 0.5, -bridge_len * 0.5, bridge_len, bridge_len),
+            graphics::Color::WHITE,
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        // белые мосты, которые красим в режимах давности
+        let h_white = ggez::graphics::MeshBuilder::new()
+        .line(
+            &[na::Point2::new(0.0, 0.0),
+              na::Point2::new(bridge_len, 0.0)
+            ]
+            , theme.bridge_thickness
+            , graphics::Color::WHITE
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        let v_white = ggez::graphics::MeshBuilder::new()
+        .line(
+            &[na::Point2::new(0.0, 0.0),
+              na::Point2::new(0.0, bridge_len)
+            ]
+            , theme.bridge_thickness
+            , graphics::Color::WHITE
+        ).unwrap()
         .build(ctx).unwrap();
 
+        Assets {
+            node,
+            h_line,
+            v_line,
+            left,
+            right,
+            up,
+            down,
+            h_blank,
+            v_blank,
+            left_blank,
+            right_blank,
+            up_blank,
+            down_blank,
+            marker,
+            tile,
+            h_white,
+            v_white,
+        }
+    }
+
+    fn batched(&self) -> Vec<Mesh> {
+        vec!(
+            self.tile.clone(),
+            self.up_blank.clone(),
+            self.left_blank.clone(),
+            self.down_blank.clone(),
+            self.right_blank.clone(),
+            self.h_blank.clone(),
+            self.v_blank.clone(),
+            self.node.clone(),
+            self.h_line.clone(),
+            self.v_line.clone(),
+            self.h_white.clone(),
+            self.v_white.clone(),
+        )
+    }
+
+    fn marker(&self) -> &Mesh {
+        &self.marker
+    }
+}
+
+impl Walker for EngineerWalker {
+    fn new(resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> Self {
         EngineerWalker {
-            screen: Screen {
-                assets: Assets {
-                    node,
-                    h_line,
-                    v_line,
-                    left,
-                    right,
-                    up,
-                    down,
-                    h_blank,
-                    v_blank,
-                    left_blank,
-                    right_blank,
-                    up_blank,
-                    down_blank,
-                    screen_blank,
-                }
-                , resolution
-                , dim
-                , bridge_len
-                , center_coord
-            },
-            update: Update {
-                nodes: Nodes::All,
-                engineer: true,
-            },
+            screen: Screen::new(resolution, dim, theme, ctx),
             bridges: HashSet::new(),
+            chunks: ChunkCache::new(),
+            explored: Bounds::new((0, 0)),
+            visits: Visits::new(),
+            changes: Changes::new(),
+            time: 0,
+            drawn_at: 0,
+            render: RenderMode::Board,
             engineer: Engineer {
                 coord: (0, 0)
                 , or: Orientation::Up
-            }
+            },
+            rule_map: None,
+            history: vec!(),
         }
     }
 
-    fn step(&mut self) {
-        let (board_i, board_j) = self.engineer.coord;
-        let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        match &mut self.update.nodes {
-            Nodes::Some(ref mut v) => {
-                v.push((screen_i, screen_j));
-                v.push((screen_i-1, screen_j));
-                v.push((screen_i, screen_j-1));
-            },
-            _ => {},
-        }
+    fn resize(&mut self, resolution: (f32, f32), ctx: &mut Context) {
+        self.screen.resize(resolution, ctx);
+    }
 
-        let opposite_node = match self.engineer.or {
-            Orientation::Up => {
-                (board_i, board_j - 1)
-            },
-            Orientation::Left => {
-                (board_i - 1, board_j)
-            },
-            Orientation::Down => {
-                (board_i, board_j + 1)
-            },
-            Orientation::Right => {
-                (board_i + 1, board_j)
-            }
-        };
+    fn set_theme(&mut self, theme: &Theme, ctx: &mut Context) {
+        self.screen.set_theme(theme, ctx);
+        self.chunks.clear();
+    }
 
-        if self.bridges.contains(&(board_i, board_j, opposite_node.0, opposite_node.1))
-        || self.bridges.contains(&(opposite_node.0, opposite_node.1, board_i, board_j)) {
-            self.bridges.remove(&(board_i, board_j, opposite_node.0, opposite_node.1));
-            self.bridges.remove(&(opposite_node.0, opposite_node.1, board_i, board_j));
+    fn zoom(&mut self, factor: f32, around: (f32, f32), ctx: &mut Context) {
+        self.screen.zoom(factor, around, ctx);
+    }
 
-            self.engineer.coord = opposite_node;
-            self.engineer.or = match self.engineer.or {
-                Orientation::Up => Orientation::Left,
-                Orientation::Left => Orientation::Down,
-                Orientation::Down => Orientation::Right,
-                Orientation::Right => Orientation::Up,
-            }
+    fn pan(&mut self, dx: f32, dy: f32) {
+        self.screen.pan(dx, dy);
+    }
+
+    fn toggle_follow(&mut self) {
+        self.screen.toggle_follow(self.engineer.coord);
+    }
+
+    fn toggle(&mut self, x: f32, y: f32) {
+        let bridge_len = self.screen.camera.cell_size;
+        let (fx, fy) = (x / bridge_len, y / bridge_len);
+
+        // ближайший горизонтальный и ближайший вертикальный мост, берём тот, что ближе
+        let h_dist = (fy - fy.round()).abs();
+        let v_dist = (fx - fx.round()).abs();
+        let (a, b) = if h_dist <= v_dist {
+            let a = (fx.floor() as i64, fy.round() as i64);
+            (a, (a.0 + 1, a.1))
         } else {
-            self.bridges.insert((board_i, board_j, opposite_node.0, opposite_node.1));
+            let a = (fx.round() as i64, fy.floor() as i64);
+            (a, (a.0, a.1 + 1))
+        };
+
+        let (board_a, board_b) = (self.screen_to_board(a.0, a.1), self.screen_to_board(b.0, b.1));
+        let bridge = (board_a.0, board_a.1, board_b.0, board_b.1);
+        let reverse = (board_b.0, board_b.1, board_a.0, board_a.1);
+        if self.bridges.contains(&bridge) || self.bridges.contains(&reverse) {
+            self.bridges.remove(&bridge);
+            self.bridges.remove(&reverse);
+        } else {
+            self.bridges.insert(bridge);
+        }
+        self.record(board_a, board_b);
+
+        self.chunks.invalidate(board_a.0, board_a.1);
+        self.chunks.invalidate(board_b.0, board_b.1);
+        self.screen.mark(a.0, a.1);
+        self.screen.mark(b.0, b.1);
+        self.screen.redraw_walker();
+    }
 
+    fn place(&mut self, x: f32, y: f32) {
+        let bridge_len = self.screen.camera.cell_size;
+        let (screen_i, screen_j) = ((x / bridge_len).round() as i64, (y / bridge_len).round() as i64);
+        let node = self.screen_to_board(screen_i, screen_j);
+
+        if node == self.engineer.coord {
             self.engineer.or = match self.engineer.or {
                 Orientation::Up => Orientation::Right,
                 Orientation::Right => Orientation::Down,
                 Orientation::Down => Orientation::Left,
                 Orientation::Left => Orientation::Up,
-            }
+            };
+        } else {
+            let (old_i, old_j) = self.board_to_screen(self.engineer.coord.0, self.engineer.coord.1);
+            self.screen.mark(old_i, old_j);
+            self.engineer.coord = node;
+            self.explored.extend(node);
         }
 
+        self.screen.mark(screen_i, screen_j);
+        self.screen.redraw_walker();
+    }
 
+    fn redraw(&mut self) {
+        self.screen.redraw();
+    }
 
-        let (new_screen_i, new_screen_j) = self.board_to_screen(self.engineer.coord.0, self.engineer.coord.1);
-        if new_screen_i < 0 {
-            self.screen.center_coord.0 -= 1;
-            self.update.nodes = Nodes::All;
-        }
-        if new_screen_i > self.screen.dim.0 {
-            self.screen.center_coord.0 += 1;
-            self.update.nodes = Nodes::All;
-        }
-        if new_screen_j < 0 {
-            self.screen.center_coord.1 -= 1;
-            self.update.nodes = Nodes::All;
-        }
-        if new_screen_j > self.screen.dim.1 {
-            self.screen.center_coord.1 += 1;
-            self.update.nodes = Nodes::All;
-        }
+    fn set_up(&mut self, setup: &Setup) {
+        let layout = setup.layout(2);
+        // закрашенные клетки — узлы, соседние узлы фигуры соединены мостами
+        let nodes: HashSet<(i64, i64)> = layout.cells.into_iter().map(|(cell, _)| cell).collect();
+        self.bridges = nodes.iter()
+        .flat_map(|&(i, j)| vec!(((i, j), (i + 1, j)), ((i, j), (i, j + 1))))
+        .filter(|(_, b)| nodes.contains(b))
+        .chain(layout.bridges)
+        .map(|(a, b)| edge(a, b))
+        .collect();
+
+        self.engineer.coord = setup.start;
+        self.engineer.or = match setup.heading.rem_euclid(4) {
+            0 => Orientation::Up,
+            1 => Orientation::Right,
+            2 => Orientation::Down,
+            _ => Orientation::Left,
+        };
+        self.explored = Bounds::new(setup.start);
+        self.screen.camera.center_coord = setup.start;
+        self.chunks.clear();
+        self.redraw();
+    }
 
-        let (board_i, board_j) = self.engineer.coord;
-        let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        match &mut self.update.nodes {
-            Nodes::Some(ref mut v) => v.push((screen_i, screen_j)),
-            _ => {},
+    // правило из двух букв: поворот после постройки моста и после перехода
+    fn set_rule_map(&mut self, map: &RuleMap) -> Result<(), String> {
+        map.check_letters("engineer", 2)?;
+        self.rule_map = Some(map.clone());
+        Ok(())
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            coord: self.engineer.coord,
+            heading: match self.engineer.or {
+                Orientation::Up => "up",
+                Orientation::Down => "down",
+                Orientation::Left => "left",
+                Orientation::Right => "right",
+            },
+            population: self.bridges.len(),
+            population_label: "bridges",
+            explored: self.explored,
         }
+    }
 
-        self.update.engineer = true;
+    fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render = mode;
+        self.chunks.clear();
+        self.redraw();
     }
 
-    fn step_back(&mut self) {
-        /*let (board_i, board_j) = self.engineer.coord;
+    fn step(&mut self) -> bool {
+        let (board_i, board_j) = self.engineer.coord;
         let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        match &mut self.update.cells {
-            Cells::Some(ref mut v) => {
-                v.push((screen_i, screen_j));
-                v.push((screen_i - 1, screen_j));
-                v.push((screen_i + 1, screen_j));
-                v.push((screen_i, screen_j - 1));
-                v.push((screen_i, screen_j + 1));
-            },
-            _ => {},
-        }
+        self.screen.mark(screen_i, screen_j);
+        self.screen.mark(screen_i-1, screen_j);
+        self.screen.mark(screen_i, screen_j-1);
 
-        // шагаем
-        match &self.engineer.or {
-            Orientation::Up => self.engineer.coord.1 += 1,
-            Orientation::Left => self.engineer.coord.0 += 1,
-            Orientation::Down => self.engineer.coord.1 -= 1,
-            Orientation::Right => self.engineer.coord.0 -= 1,
+        self.chunks.invalidate(board_i, board_j);
+        self.time += 1;
+
+        let opposite_node = ahead((board_i, board_j), self.engineer.or);
+
+        let (build, cross) = self.turns((board_i, board_j));
+        let crossed = self.bridges.contains(&(board_i, board_j, opposite_node.0, opposite_node.1))
+        || self.bridges.contains(&(opposite_node.0, opposite_node.1, board_i, board_j));
+        if self.history.len() == HISTORY {
+            self.history.drain(..HISTORY / 2);
         }
+        self.history.push(((board_i, board_j), self.engineer.or, crossed));
 
-        let (board_i, board_j) = self.engineer.coord;
-        if self.board.contains(&(board_i, board_j)) {
-            match &self.engineer.or {
-                Orientation::Up => self.engineer.or = Orientation::Left,
-                Orientation::Left => self.engineer.or = Orientation::Down,
-                Orientation::Down => self.engineer.or = Orientation::Right,
-                Orientation::Right => self.engineer.or = Orientation::Up,
-            }
+        if crossed {
+            self.bridges.remove(&(board_i, board_j, opposite_node.0, opposite_node.1));
+            self.bridges.remove(&(opposite_node.0, opposite_node.1, board_i, board_j));
+            self.chunks.invalidate(opposite_node.0, opposite_node.1);
+            self.record((board_i, board_j), opposite_node);
 
-            self.board.remove(&(board_i, board_j));
+            self.engineer.coord = opposite_node;
+            self.turn(cross);
         } else {
-            match &self.engineer.or {
-                Orientation::Up => self.engineer.or = Orientation::Right,
-                Orientation::Left => self.engineer.or = Orientation::Up,
-                Orientation::Down => self.engineer.or = Orientation::Left,
-                Orientation::Right => self.engineer.or = Orientation::Down,
-            }
+            self.bridges.insert((board_i, board_j, opposite_node.0, opposite_node.1));
+            self.chunks.invalidate(opposite_node.0, opposite_node.1);
+            self.record((board_i, board_j), opposite_node);
 
-            self.board.insert((board_i, board_j));
+            self.turn(build);
         }
 
-        let (new_screen_i, new_screen_j) = self.board_to_screen(self.engineer.coord.0, self.engineer.coord.1);
-        if new_screen_i < 0 {
-            self.screen.center_coord.0 -= 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_i > self.screen.dim.0 {
-            self.screen.center_coord.0 += 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_j < 0 {
-            self.screen.center_coord.1 -= 1;
-            self.update.cells = Cells::All;
-        }
-        if new_screen_j > self.screen.dim.1 {
-            self.screen.center_coord.1 += 1;
-            self.update.cells = Cells::All;
+        self.screen.track(self.engineer.coord);
+        self.screen.mark_cell(self.engineer.coord);
+
+        self.explored.extend(self.engineer.coord);
+        self.chunks.invalidate(self.engineer.coord.0, self.engineer.coord.1);
+        if self.visits.visit(self.engineer.coord) && self.render == RenderMode::Visits {
+            self.chunks.clear();
+            self.screen.redraw();
         }
+        self.screen.limit_dirty();
+        true
+    }
 
-        let (board_i, board_j) = self.engineer.coord;
-        let (screen_i, screen_j) = self.board_to_screen(board_i, board_j);
-        match &mut self.update.cells {
-            Cells::Some(ref mut v) => v.push((screen_i, screen_j)),
-            _ => {},
+    fn step_back(&mut self) -> bool {
+        let (node, or, crossed) = match self.history.pop() {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        self.visits.unvisit(self.engineer.coord);
+        self.chunks.invalidate(self.engineer.coord.0, self.engineer.coord.1);
+        let (screen_i, screen_j) = self.board_to_screen(self.engineer.coord.0, self.engineer.coord.1);
+        self.mark_around(screen_i, screen_j);
+
+        self.time = self.time.saturating_sub(1);
+        let opposite_node = ahead(node, or);
+        if crossed {
+            self.bridges.insert(edge(node, opposite_node));
+        } else {
+            self.bridges.remove(&(node.0, node.1, opposite_node.0, opposite_node.1));
+            self.bridges.remove(&(opposite_node.0, opposite_node.1, node.0, node.1));
         }
+        self.changes.undo(edge(node, opposite_node), self.time);
+        self.chunks.invalidate(node.0, node.1);
+        self.chunks.invalidate(opposite_node.0, opposite_node.1);
+
+        self.engineer.coord = node;
+        self.engineer.or = or;
 
-        self.update.engineer = true;*/
+        self.screen.track(self.engineer.coord);
+        let (screen_i, screen_j) = self.board_to_screen(node.0, node.1);
+        self.mark_around(screen_i, screen_j);
+
+        self.screen.limit_dirty();
+        true
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        match self.update.nodes {
-            Nodes::Some(ref nodes) => {
-                for &(i, j) in nodes {
-                    self.draw_node(ctx, i, j)?;
-                }
+    fn can_step_back(&self) -> bool {
+        !self.history.is_empty()
+    }
 
-                self.update.nodes = Nodes::Some(vec!());
-            },
-            Nodes::All => {
-                graphics::draw(ctx, &self.screen.assets.screen_blank, graphics::DrawParam::default()
-                .dest(na::Point2::new(0., 0.)))?;
-                for i in 0..self.screen.dim.0 {
-                    for j in 0..self.screen.dim.1 {
-                        self.draw_node(ctx, i, j)?;
-                    }
-                }
-                self.update.nodes = Nodes::Some(vec!());
-            },
+    fn frame(&mut self) -> Frame {
+        // при раскраске по давности с каждым шагом меняется вся доска
+        if self.render == RenderMode::Recency && self.drawn_at != self.time {
+            self.chunks.clear();
+            self.screen.redraw();
         }
+        self.drawn_at = self.time;
 
-        if self.update.engineer {
-            self.draw_engineer(ctx)?;
-            self.update.engineer = false;
+        if self.screen.lod() {
+            return self.draw_lod();
         }
 
-        graphics::present(ctx)?;
-        Ok(())
+        let mut frame = Frame::new();
+        for (i, j) in self.screen.dirty(&mut frame) {
+            self.draw_node(i, j);
+        }
+        if self.screen.finish(&mut frame) {
+            self.draw_engineer(&mut frame);
+        }
+
+        frame
+    }
+
+    fn recycle(&mut self, batches: Vec<MeshBatch>) {
+        self.screen.recycle(batches);
     }
 }
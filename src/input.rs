@@ -0,0 +1,178 @@
+use ggez::*;
+use ggez::event::{KeyCode, KeyMods};
+use ggez::input::keyboard;
+
+// команды, на которые в keys.cfg вешаются клавиши
+#[derive(Clone, Copy, PartialEq)]
+pub enum Command {
+    ToggleStream,
+    Step,
+    StepBack,
+    Faster,
+    Slower,
+    RunUntil,
+    EraseDigit,
+    Follow,
+    Theme,
+    Render,
+    Hud,
+    Plots,
+    LogLog,
+    Menu,
+    Symmetry,
+    Export,
+}
+
+impl Command {
+    pub fn parse(name: &str) -> Option<Command> {
+        let command = match name {
+            "toggle-stream" => Command::ToggleStream,
+            "step" => Command::Step,
+            "step-back" => Command::StepBack,
+            "faster" => Command::Faster,
+            "slower" => Command::Slower,
+            "run-until" => Command::RunUntil,
+            "erase-digit" => Command::EraseDigit,
+            "follow" => Command::Follow,
+            "theme" => Command::Theme,
+            "render" => Command::Render,
+            "hud" => Command::Hud,
+            "plots" => Command::Plots,
+            "log-log" => Command::LogLog,
+            "menu" => Command::Menu,
+            "symmetry" => Command::Symmetry,
+            "export" => Command::Export,
+            _ => return None,
+        };
+
+        Some(command)
+    }
+}
+
+#[derive(Clone)]
+pub struct Binding {
+    pub command: Command,
+    pub key: KeyCode,
+    pub mods: KeyMods,
+    // пока клавиша зажата, команда повторяется
+    pub repeat: bool,
+}
+
+impl Binding {
+    pub fn new(command: Command, key: KeyCode, repeat: bool) -> Binding {
+        Binding {
+            command,
+            key,
+            mods: KeyMods::NONE,
+            repeat,
+        }
+    }
+
+    // ещё один тик: held — сколько тиков подряд привязка зажата;
+    // true, если команда срабатывает сейчас — при нажатии или при повторе
+    pub fn tick(&self, pressed: bool, mods: KeyMods, held: &mut u32) -> bool {
+        if !pressed || mods != self.mods {
+            *held = 0;
+            return false;
+        }
+
+        *held += 1;
+        let repeated = self.repeat && *held > REPEAT_DELAY && (*held - REPEAT_DELAY).is_multiple_of(REPEAT_EVERY);
+        *held == 1 || repeated
+    }
+}
+
+// раскладка по умолчанию, на случай если файла с клавишами нет
+pub fn default_bindings() -> Vec<Binding> {
+    vec!(
+        Binding::new(Command::ToggleStream, KeyCode::Space, false),
+        Binding::new(Command::Step, KeyCode::Right, true),
+        Binding::new(Command::StepBack, KeyCode::Left, true),
+        Binding::new(Command::Faster, KeyCode::Up, false),
+        Binding::new(Command::Slower, KeyCode::Down, false),
+        Binding::new(Command::RunUntil, KeyCode::Return, false),
+        Binding::new(Command::EraseDigit, KeyCode::Back, true),
+        Binding::new(Command::Follow, KeyCode::F, false),
+        Binding::new(Command::Theme, KeyCode::T, false),
+        Binding::new(Command::Render, KeyCode::V, false),
+        Binding::new(Command::Hud, KeyCode::H, false),
+        Binding::new(Command::Plots, KeyCode::P, false),
+        Binding::new(Command::LogLog, KeyCode::L, false),
+        Binding::new(Command::Menu, KeyCode::M, false),
+        Binding::new(Command::Symmetry, KeyCode::S, false),
+        Binding::new(Command::Export, KeyCode::E, false),
+    )
+}
+
+// через сколько тиков зажатая клавиша начинает повторять и как часто
+const REPEAT_DELAY: u32 = 12;
+const REPEAT_EVERY: u32 = 2;
+
+pub struct Input {
+    bindings: Vec<Binding>,
+    // сколько тиков подряд зажата каждая привязка, 0 — отпущена
+    held: Vec<u32>,
+}
+
+impl Input {
+    pub fn new(bindings: Vec<Binding>) -> Input {
+        Input {
+            held: vec!(0; bindings.len()),
+            bindings,
+        }
+    }
+
+    // команды, сработавшие на этом тике: в момент нажатия и потом при повторе
+    pub fn poll(&mut self, ctx: &Context) -> Vec<Command> {
+        let mods = keyboard::active_mods(ctx);
+        let mut fired = vec!();
+
+        for (binding, held) in self.bindings.iter().zip(self.held.iter_mut()) {
+            if binding.tick(keyboard::is_key_pressed(ctx, binding.key), mods, held) {
+                fired.push(binding.command);
+            }
+        }
+
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binding_fires_on_press_and_then_repeats() {
+        let binding = Binding::new(Command::Step, KeyCode::Right, true);
+        let mut held = 0;
+        let fired: Vec<u32> = (1..=20).filter(|_| binding.tick(true, KeyMods::NONE, &mut held)).collect();
+        assert_eq!(fired, vec!(1, 14, 16, 18, 20));
+
+        // отпустили — следующее нажатие снова срабатывает сразу
+        assert!(!binding.tick(false, KeyMods::NONE, &mut held));
+        assert_eq!(held, 0);
+        assert!(binding.tick(true, KeyMods::NONE, &mut held));
+    }
+
+    #[test]
+    fn binding_without_repeat_fires_once() {
+        let binding = Binding::new(Command::Theme, KeyCode::T, false);
+        let mut held = 0;
+        assert_eq!((0..30).filter(|_| binding.tick(true, KeyMods::NONE, &mut held)).count(), 1);
+    }
+
+    #[test]
+    fn binding_needs_exactly_its_modifiers() {
+        let plain = Binding::new(Command::Step, KeyCode::D, false);
+        let shifted = Binding {
+            mods: KeyMods::SHIFT,
+            ..Binding::new(Command::StepBack, KeyCode::D, false)
+        };
+        let (mut plain_held, mut shifted_held) = (0, 0);
+
+        assert!(!plain.tick(true, KeyMods::SHIFT, &mut plain_held));
+        assert!(shifted.tick(true, KeyMods::SHIFT, &mut shifted_held));
+        assert!(!shifted.tick(true, KeyMods::SHIFT | KeyMods::CTRL, &mut shifted_held));
+        assert!(plain.tick(true, KeyMods::NONE, &mut plain_held));
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs;
+use ggez::event::{KeyCode, KeyMods};
+
+use crate::lib::{config_lines, default_bindings, key_value, Binding, Command};
+
+// Файл с клавишами выглядит так:
+//
+//   step = Right Shift+D repeat
+//   theme = Ctrl+T
+//
+// каждая строчка заменяет все клавиши своей команды,
+// repeat — повторять команду, пока клавиша зажата;
+// чего нет в файле, остаётся как по умолчанию
+pub fn load(path: &str) -> Vec<Binding> {
+    let mut bindings = default_bindings();
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return bindings,
+    };
+
+    for e in rebind(&mut bindings, &text) {
+        eprintln!("{}:{}", path, e);
+    }
+
+    bindings
+}
+
+// строчки файла поверх привязок; сломанные пропускаются, их ошибки — с номером строчки
+fn rebind(bindings: &mut Vec<Binding>, text: &str) -> Vec<String> {
+    let mut errors = vec!();
+
+    for (n, line) in config_lines(text) {
+        match parse(line) {
+            Ok((command, keys)) => {
+                bindings.retain(|binding| binding.command != command);
+                bindings.extend(keys);
+            },
+            Err(e) => errors.push(format!("{}: {}", n, e)),
+        }
+    }
+
+    errors
+}
+
+fn parse(line: &str) -> Result<(Command, Vec<Binding>), String> {
+    let (name, words) = key_value(line)?;
+    let command = Command::parse(name).ok_or_else(|| format!("unknown command `{}`", name))?;
+    let repeat = words.contains(&"repeat");
+
+    let mut keys = vec!();
+    for &word in words.iter().filter(|&&word| word != "repeat") {
+        let (key, mods) = combo(word)?;
+        keys.push(Binding {
+            command,
+            key,
+            mods,
+            repeat,
+        });
+    }
+
+    Ok((command, keys))
+}
+
+// Ctrl+Shift+S: модификаторы через плюс, клавиша последней
+fn combo(word: &str) -> Result<(KeyCode, KeyMods), String> {
+    let mut parts: Vec<&str> = word.split('+').collect();
+    let name = parts.pop().unwrap();
+
+    let mut mods = KeyMods::NONE;
+    for part in parts {
+        mods |= match part {
+            "Shift" => KeyMods::SHIFT,
+            "Ctrl" => KeyMods::CTRL,
+            "Alt" => KeyMods::ALT,
+            "Logo" | "Super" => KeyMods::LOGO,
+            _ => return Err(format!("unknown modifier `{}` in `{}`", part, word)),
+        };
+    }
+
+    let key = key(name).ok_or_else(|| format!("unknown key `{}`", name))?;
+    Ok((key, mods))
+}
+
+fn key(name: &str) -> Option<KeyCode> {
+    let key = match name {
+        "A" => KeyCode::A, "B" => KeyCode::B, "C" => KeyCode::C, "D" => KeyCode::D,
+        "E" => KeyCode::E, "F" => KeyCode::F, "G" => KeyCode::G, "H" => KeyCode::H,
+        "I" => KeyCode::I, "J" => KeyCode::J, "K" => KeyCode::K, "L" => KeyCode::L,
+        "M" => KeyCode::M, "N" => KeyCode::N, "O" => KeyCode::O, "P" => KeyCode::P,
+        "Q" => KeyCode::Q, "R" => KeyCode::R, "S" => KeyCode::S, "T" => KeyCode::T,
+        "U" => KeyCode::U, "V" => KeyCode::V, "W" => KeyCode::W, "X" => KeyCode::X,
+        "Y" => KeyCode::Y, "Z" => KeyCode::Z,
+        "0" => KeyCode::Key0, "1" => KeyCode::Key1, "2" => KeyCode::Key2, "3" => KeyCode::Key3,
+        "4" => KeyCode::Key4, "5" => KeyCode::Key5, "6" => KeyCode::Key6, "7" => KeyCode::Key7,
+        "8" => KeyCode::Key8, "9" => KeyCode::Key9,
+        "F1" => KeyCode::F1, "F2" => KeyCode::F2, "F3" => KeyCode::F3, "F4" => KeyCode::F4,
+        "F5" => KeyCode::F5, "F6" => KeyCode::F6, "F7" => KeyCode::F7, "F8" => KeyCode::F8,
+        "F9" => KeyCode::F9, "F10" => KeyCode::F10, "F11" => KeyCode::F11, "F12" => KeyCode::F12,
+        "Space" => KeyCode::Space,
+        "Return" | "Enter" => KeyCode::Return,
+        "Back" | "Backspace" => KeyCode::Back,
+        "Tab" => KeyCode::Tab,
+        "Delete" => KeyCode::Delete,
+        "Insert" => KeyCode::Insert,
+        "Home" => KeyCode::Home,
+        "End" => KeyCode::End,
+        "PageUp" => KeyCode::PageUp,
+        "PageDown" => KeyCode::PageDown,
+        "Left" => KeyCode::Left,
+        "Right" => KeyCode::Right,
+        "Up" => KeyCode::Up,
+        "Down" => KeyCode::Down,
+        "Minus" => KeyCode::Minus,
+        "Equals" => KeyCode::Equals,
+        "Comma" => KeyCode::Comma,
+        "Period" => KeyCode::Period,
+        "Slash" => KeyCode::Slash,
+        "LBracket" => KeyCode::LBracket,
+        "RBracket" => KeyCode::RBracket,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_with_modifiers_and_repeat() {
+        let (command, keys) = parse("step = Right Shift+D repeat").unwrap();
+        assert!(command == Command::Step);
+        assert_eq!(keys.len(), 2);
+        assert_eq!((keys[0].key, keys[0].mods), (KeyCode::Right, KeyMods::NONE));
+        assert_eq!((keys[1].key, keys[1].mods), (KeyCode::D, KeyMods::SHIFT));
+        assert!(keys.iter().all(|binding| binding.repeat && binding.command == Command::Step));
+
+        let (_, keys) = parse("theme = Ctrl+Alt+T").unwrap();
+        assert_eq!(keys[0].mods, KeyMods::CTRL | KeyMods::ALT);
+        assert!(!keys[0].repeat);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse("step Right").is_err());
+        assert!(parse("jump = J").is_err());
+        assert!(parse("step = Hyper+J").is_err());
+        assert!(parse("step = Kanji").is_err());
+    }
+
+    #[test]
+    fn a_line_replaces_only_its_own_command() {
+        let mut bindings = default_bindings();
+        let errors = rebind(&mut bindings, "# комментарий\n\nstep = D Shift+Right   # хвост\njump = J\n");
+        assert_eq!(errors, vec!(String::from("4: unknown command `jump`")));
+
+        let steps: Vec<_> = bindings.iter().filter(|b| b.command == Command::Step).map(|b| (b.key, b.mods)).collect();
+        assert_eq!(steps, vec!((KeyCode::D, KeyMods::NONE), (KeyCode::Right, KeyMods::SHIFT)));
+        // остальные команды остались как по умолчанию
+        assert!(bindings.iter().any(|b| b.command == Command::StepBack && b.key == KeyCode::Left));
+        assert_eq!(bindings.len(), default_bindings().len() + 1);
+    }
+}
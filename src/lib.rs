@@ -1,110 +1,915 @@
 use ggez::*;
-use std::collections::HashSet;
-use ggez::{nalgebra as na};
-use ggez::graphics::{self, DrawMode, Mesh};
-use ggez::event::{EventHandler, KeyCode, KeyMods};
-use ggez::input::keyboard;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use nalgebra as na;
+use ggez::graphics::{self, DrawMode, MeshBatch};
+use ggez::event::MouseButton;
+use ggez::input::mouse;
 
+// модули библиотеки лежат рядом с lib.rs: бинарник собирает lib.rs ещё раз своим модулем lib,
+// и без path искал бы их в src/lib/
+#[path = "render.rs"]
+mod render;
+#[path = "plots.rs"]
+mod plots;
+#[path = "config.rs"]
+mod config;
+#[path = "replay.rs"]
+mod replay;
+#[path = "input.rs"]
+mod input;
+#[path = "sim.rs"]
+mod sim;
+#[path = "symmetry.rs"]
+mod symmetry;
+#[path = "setup.rs"]
+mod setup;
+#[path = "schedule.rs"]
+mod schedule;
+#[path = "rules.rs"]
+mod rules;
 
-pub trait Walker {
-    fn new(resolution: (f32, f32), dim: i64, ctx: &mut Context) -> Self;
+pub use render::*;
+pub use plots::*;
+pub use config::*;
+pub use replay::*;
+pub use input::*;
+pub use sim::*;
+pub use symmetry::*;
+pub use setup::*;
+pub use schedule::*;
+pub use rules::*;
 
-    fn step(&mut self);
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    pub paper: [f32; 4],
+    pub grid: [f32; 4],
+    pub ink: [f32; 4],
+    pub roads: [f32; 4],
+    pub walker: [f32; 4],
+    pub grid_thickness: f32,
+    pub road_thickness: f32,
+    pub bridge_thickness: f32,
+    pub node_radius: f32,
+}
+
+impl Theme {
+    // исходная бумажная тема, на случай если файла с темами нет
+    pub fn paper() -> Theme {
+        Theme {
+            name: String::from("paper"),
+            paper: [0.8, 0.8, 0.6, 1.0],
+            grid: [0.4, 0.0, 0.2, 1.0],
+            ink: [0.05, 0.0, 0.0, 1.0],
+            roads: [0.2, 0.0, 0.5, 1.0],
+            walker: [0.8, 0.0, 0.0, 1.0],
+            grid_thickness: 2.0,
+            road_thickness: 4.0,
+            bridge_thickness: 6.0,
+            node_radius: 8.0,
+        }
+    }
+}
+
+// ходок живёт в рабочем потоке симуляции, а рисуется из главного
+pub trait Walker: Send {
+    fn new(resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> Self where Self: Sized;
+
+    fn set_theme(&mut self, theme: &Theme, ctx: &mut Context);
+
+    fn resize(&mut self, resolution: (f32, f32), ctx: &mut Context);
+
+    fn zoom(&mut self, factor: f32, around: (f32, f32), ctx: &mut Context);
+
+    fn pan(&mut self, dx: f32, dy: f32);
+
+    fn toggle_follow(&mut self);
+
+    // правка доски кликом: x, y в пикселях окна
+    fn toggle(&mut self, x: f32, y: f32);
+
+    // перенести ходока в клетку, а если он уже там, повернуть
+    fn place(&mut self, x: f32, y: f32);
+
+    // перерисовать всё с нуля на следующем кадре
+    fn redraw(&mut self);
 
-    fn step_back(&mut self);
+    fn stats(&self) -> Stats;
+
+    fn set_render_mode(&mut self, mode: RenderMode);
+
+    // false — ходок не сдвинулся, и шаг не считается
+    fn step(&mut self) -> bool;
+
+    // false — откатывать больше нечего
+    fn step_back(&mut self) -> bool;
+
+    // есть ли ещё куда откатываться; у кого история ограничена, те её теряют
+    fn can_step_back(&self) -> bool {
+        true
+    }
+
+    // начать с доски и места из настройки; зовётся сразу после new
+    fn set_up(&mut self, setup: &Setup);
+
+    // сменить правило на строчку вроде LLRR; false — этот ходок так не умеет
+    fn set_rule(&mut self, _rule: &str) -> bool {
+        false
+    }
+
+    // своё правило в каждой области карты; ошибка — почему ходок так не может
+    fn set_rule_map(&mut self, _map: &RuleMap) -> Result<(), String> {
+        Err(String::from("this walker ignores the rule map"))
+    }
+
+    // непустые клетки с состояниями, если доска у ходока из клеток
+    fn cells(&self) -> Option<Vec<((i64, i64), i64)>> {
+        None
+    }
+
+    // симметрии доски; кто не умеет их искать, всегда несимметричен
+    fn symmetries(&self) -> Vec<Symmetry> {
+        vec!()
+    }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult;
+    // что перерисовать с прошлого кадра; зовётся под замком симуляции, рисуется после
+    fn frame(&mut self) -> Frame;
+
+    // батчи прошлого кадра, уже нарисованные и пустые, чтобы не заводить их заново
+    fn recycle(&mut self, _batches: Vec<MeshBatch>) {}
 }
 
-pub enum Mode {
-    Stream(u64),
-    StepByStep,
+// как построить ходока: по размеру окна, стороне экрана в клетках и теме
+pub type MakeWalker = Box<dyn Fn((f32, f32), i64, &Theme, &mut Context) -> Box<dyn Walker>>;
+
+// строчка в меню ходоков: имя и как его построить
+pub struct WalkerKind {
+    pub name: String,
+    pub make: MakeWalker,
 }
 
-pub struct Buttons {
-    space: bool,
-    right: bool,
-    left: bool,
+impl WalkerKind {
+    pub fn of<W: Walker + 'static>(name: &str) -> WalkerKind {
+        WalkerKind {
+            name: String::from(name),
+            make: Box::new(|resolution, dim, theme, ctx| Box::new(W::new(resolution, dim, theme, ctx))),
+        }
+    }
 }
 
-pub struct State<W: Walker> {
-    mode: Mode,
-    buttons: Buttons,
-    resolution: (f32, f32),
-    walker: W,
+// ходок, который понимает любое правило-строчку; к нему уходит расписание,
+// если выбранный ходок своё правило менять не умеет
+pub const TURMITE: &str = "turmite";
+
+#[derive(Clone, Copy)]
+pub struct Bounds {
+    pub min: (i64, i64),
+    pub max: (i64, i64),
+}
+
+impl Bounds {
+    pub fn new(coord: (i64, i64)) -> Bounds {
+        Bounds {
+            min: coord,
+            max: coord,
+        }
+    }
+
+    pub fn extend(&mut self, coord: (i64, i64)) {
+        self.min = (self.min.0.min(coord.0), self.min.1.min(coord.1));
+        self.max = (self.max.0.max(coord.0), self.max.1.max(coord.1));
+    }
+
+    pub fn area(&self) -> i64 {
+        (self.max.0 - self.min.0 + 1) * (self.max.1 - self.min.1 + 1)
+    }
+}
+
+pub struct Stats {
+    pub coord: (i64, i64),
+    pub heading: &'static str,
+    // чёрные клетки у муравья, мосты у инженера
+    pub population: usize,
+    pub population_label: &'static str,
+    // где ходок успел побывать
+    pub explored: Bounds,
+}
+
+// Детерминированный генератор (splitmix64): одно и то же зерно
+// даёт одну и ту же доску на любой машине и в любой версии
+#[derive(Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    // отдельный поток для каждого n: случайность шага задают зерно и номер шага,
+    // так что шаг назад и повтор прогона вытягивают те же числа
+    pub fn stream(seed: u64, n: u64) -> Rng {
+        let mixed = Rng::new(seed).next_u64() ^ Rng::new(!n).next_u64();
+        Rng::new(mixed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // число от 0 до 1, не включая 1
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+}
+
+// шагов в секунду при нулевой скорости
+const BASE_RATE: f64 = 30.0;
+
+// степень двойки: во сколько раз быстрее или медленнее BASE_RATE
+#[derive(Clone, Copy, PartialEq)]
+pub struct Speed(pub i32);
+
+impl Speed {
+    const SLOWEST: i32 = -5;
+    // самая верхняя ступенька — без ограничений
+    const UNLIMITED: i32 = 23;
+
+    pub fn faster(self) -> Speed {
+        Speed((self.0 + 1).min(Speed::UNLIMITED))
+    }
+
+    pub fn slower(self) -> Speed {
+        Speed((self.0 - 1).max(Speed::SLOWEST))
+    }
+
+    // None — сколько успеем
+    pub fn per_second(self) -> Option<f64> {
+        if self.0 >= Speed::UNLIMITED {
+            None
+        } else {
+            Some(BASE_RATE * 2f64.powi(self.0))
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self.per_second() {
+            None => String::from("as fast as possible"),
+            Some(rate) if rate >= 1.0 => format!("{:.0} steps/s", rate),
+            Some(rate) => format!("1 step/{:.0} s", 1.0 / rate),
+        }
+    }
+}
+
+pub enum Mode {
+    Stream(Speed),
+    StepByStep,
 }
 
+pub struct State {
+    input: Input,
+    resolution: (f32, f32),
+    // сторона экрана в клетках, с которой стартует новый ходок
+    dim: i64,
+    worker: Worker,
+    walkers: Vec<WalkerKind>,
+    kind: usize,
+    show_menu: bool,
+    themes: Vec<Theme>,
+    theme: usize,
+    render_mode: RenderMode,
+    speed: Speed,
+    // цифры номера шага, которые сейчас набирают
+    target_input: String,
+    show_hud: bool,
+    // замер скорости: время и номер шага в начале окна
+    rate_window: (f64, u64),
+    steps_per_second: f64,
+    panel_size: (f32, f32),
+    plots: Plots,
+    show_plots: bool,
+    // сколько пикселей мышь прошла с зажатой кнопкой: клик это или перетаскивание
+    dragged: f32,
+    recording: Option<(String, File)>,
+    // пока идёт проигрывание, клавиатура и мышь не слушаются
+    replay: Option<Replay>,
+    // с чего начинает каждый новый ходок
+    setup: Setup,
+    // карта правил для каждого нового ходока и принял ли её нынешний, а если нет — почему
+    rule_map: Option<RuleMap>,
+    mapped: Result<(), String>,
+    // в каком формате сохранять доску и что сохранили последним
+    export_format: String,
+    exported: Option<String>,
+    // картинки кусков доски для мелкого масштаба
+    chunk_images: ChunkImages,
+    // батчи нарисованного кадра, которые вернутся ходоку
+    spent: Vec<MeshBatch>,
+}
 
-impl<W: Walker> State<W> {
-    pub fn new(ctx: &mut Context, dim: i64) -> Self {
-        let resolution = (ctx.conf.window_mode.width, ctx.conf.window_mode.height);
+impl State {
+    // если ходок не выбран заранее, начинаем с первого и показываем меню
+    pub fn new(ctx: &mut Context, dim: i64, themes: Vec<Theme>, bindings: Vec<Binding>, walkers: Vec<WalkerKind>, kind: Option<usize>) -> Self {
+        let resolution = graphics::drawable_size(ctx);
+        let walker = (walkers[kind.unwrap_or(0)].make)(resolution, dim, &themes[0], ctx);
 
         State {
-            mode: Mode::StepByStep,
-            buttons: Buttons {
-                space: false,
-                right: false,
-                left: false,
-            },
+            input: Input::new(bindings),
             resolution,
-            walker: W::new(resolution, dim, ctx),
+            dim,
+            worker: Worker::spawn(Sim {
+                walker,
+                step: 0,
+                mode: Mode::StepByStep,
+                target: None,
+                hold_at: None,
+                watch: Watch::Off,
+                symmetry: vec!(),
+                symmetry_log: VecDeque::new(),
+                schedule: None,
+                phase: 0,
+                ruled: false,
+                phase_log: vec!(),
+                stalled: false,
+            }),
+            walkers,
+            kind: kind.unwrap_or(0),
+            show_menu: kind.is_none(),
+            themes,
+            theme: 0,
+            render_mode: RenderMode::Board,
+            speed: Speed(3),
+            target_input: String::new(),
+            show_hud: true,
+            rate_window: (0.0, 0),
+            steps_per_second: 0.0,
+            panel_size: (0.0, 0.0),
+            plots: Plots::new(),
+            show_plots: false,
+            dragged: 0.0,
+            recording: None,
+            replay: None,
+            setup: Setup::new(),
+            rule_map: None,
+            mapped: Ok(()),
+            export_format: String::from("png"),
+            exported: None,
+            chunk_images: ChunkImages::new(),
+            spent: vec!(),
+        }
+    }
+
+    // пишем всю сессию с самого начала: ходока, окно и скорость
+    pub fn record_to(&mut self, path: &str) {
+        match File::create(path) {
+            Ok(file) => self.recording = Some((String::from(path), file)),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return;
+            },
+        }
+
+        let step = self.worker.lock().step;
+        self.record(step, &Action::Switch(self.walkers[self.kind].name.clone()));
+        self.record(step, &Action::Resize(self.resolution.0, self.resolution.1));
+        self.record(step, &Action::Speed(self.speed.0));
+        if self.setup != Setup::new() {
+            self.record(step, &Action::Setup(self.setup.to_line()));
+        }
+        let schedule = self.worker.lock().schedule.as_ref().map(Schedule::to_line);
+        if let Some(schedule) = schedule {
+            self.record(step, &Action::Schedule(schedule));
+        }
+        if let Some(map) = self.rule_map.as_ref().map(RuleMap::to_line) {
+            self.record(step, &Action::Rules(map));
+        }
+    }
+
+    pub fn schedule(&mut self, schedule: Schedule, ctx: &mut Context) {
+        self.apply(Action::Schedule(schedule.to_line()), ctx);
+    }
+
+    // png, pgm или pbm
+    pub fn export_as(&mut self, format: &str) {
+        self.export_format = String::from(format);
+    }
+
+    // доска в файл board-<шаг> рядом с программой; симуляцию держит тот, кто зовёт
+    fn export(&mut self, sim: &Sim) {
+        let step = sim.step;
+        let cells = match sim.walker.cells() {
+            Some(cells) => cells,
+            None => {
+                eprintln!("{} has no cell board to save", self.walkers[self.kind].name);
+                return;
+            },
+        };
+
+        let path = format!("board-{}.{}", step, self.export_format);
+        match write_image(&path, &cells) {
+            Ok(corner) => self.exported = Some(format!("saved {}, top left at ({}, {})", path, corner.0, corner.1)),
+            Err(e) => eprintln!("{}: {}", path, e),
+        }
+    }
+
+    // новая настройка начинает ходока заново
+    pub fn set_up(&mut self, setup: Setup, ctx: &mut Context) {
+        self.apply(Action::Setup(setup.to_line()), ctx);
+    }
+
+    // карта правил тоже начинает ходока заново
+    pub fn map_rules(&mut self, map: RuleMap, ctx: &mut Context) {
+        self.apply(Action::Rules(map.to_line()), ctx);
+    }
+
+    pub fn replay_from(&mut self, path: &str) {
+        self.replay = Some(Replay::load(path));
+    }
+
+    fn record(&mut self, step: u64, action: &Action) {
+        let failed = match &mut self.recording {
+            Some((path, file)) => match writeln!(file, "{} {}", step, action.to_line()) {
+                Ok(()) => false,
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    true
+                },
+            },
+            None => false,
+        };
+        if failed {
+            self.recording = None;
+        }
+    }
+
+    fn apply(&mut self, action: Action, ctx: &mut Context) {
+        // замок берём через свою копию Arc, чтобы внутри можно было менять self
+        let shared = self.worker.shared.clone();
+        let mut sim = shared.0.lock().unwrap();
+        self.record(sim.step, &action);
+
+        match action {
+            Action::Play => sim.mode = Mode::Stream(self.speed),
+            Action::Pause => {
+                sim.target = None;
+                sim.mode = Mode::StepByStep;
+            },
+            Action::Step => sim.advance(),
+            Action::StepBack => sim.retreat(),
+            Action::Speed(e) => {
+                self.speed = Speed(e);
+                if let Mode::Stream(_) = sim.mode {
+                    sim.mode = Mode::Stream(self.speed);
+                }
+            },
+            Action::RunUntil(target) => {
+                // назад к цели не бежим, такую цель просто выкидываем
+                let step = sim.step;
+                sim.target = Some(target).filter(|&target| target > step);
+                if sim.target.is_some() {
+                    sim.mode = Mode::Stream(self.speed);
+                }
+            },
+            Action::Follow => sim.walker.toggle_follow(),
+            Action::Theme => {
+                self.theme = (self.theme + 1) % self.themes.len();
+                sim.walker.set_theme(&self.themes[self.theme], ctx);
+            },
+            Action::Render => {
+                self.render_mode = self.render_mode.next();
+                sim.walker.set_render_mode(self.render_mode);
+            },
+            Action::Hud => {
+                self.show_hud = !self.show_hud;
+                self.panel_size = (0.0, 0.0);
+                // стираем панель вместе с тем, что было под ней
+                sim.walker.redraw();
+            },
+            Action::Plots => {
+                self.show_plots = !self.show_plots;
+                sim.walker.redraw();
+            },
+            Action::LogLog => self.plots.log_log = !self.plots.log_log,
+            Action::Menu => {
+                self.show_menu = !self.show_menu;
+                sim.walker.redraw();
+            },
+            Action::Symmetry => {
+                sim.watch = sim.watch.next();
+                sim.symmetry_log.clear();
+                sim.symmetry = match sim.watch {
+                    Watch::Off => vec!(),
+                    _ => sim.walker.symmetries(),
+                };
+            },
+            Action::Switch(name) => match self.walkers.iter().position(|kind| kind.name == name) {
+                Some(kind) => self.switch(kind, &mut sim, ctx),
+                None => eprintln!("no walker named `{}`", name),
+            },
+            Action::Setup(line) => match Setup::parse(&line) {
+                Ok(setup) => {
+                    self.setup = setup;
+                    // меню, если оно открыто, пусть остаётся
+                    let (kind, show_menu) = (self.kind, self.show_menu);
+                    self.switch(kind, &mut sim, ctx);
+                    self.show_menu = show_menu;
+                },
+                Err(e) => eprintln!("setup: {}", e),
+            },
+            Action::Schedule(line) => match Schedule::parse(&line) {
+                Ok(schedule) => {
+                    sim.schedule = Some(schedule);
+                    sim.start_schedule();
+                    // доска ещё нетронута — раскладываем её заново под цвета нового правила
+                    if sim.ruled && sim.step == 0 {
+                        sim.walker.set_up(&self.setup);
+                    }
+                    if !sim.ruled {
+                        match self.walkers.iter().position(|kind| kind.name == TURMITE) {
+                            Some(turmite) => {
+                                eprintln!("{} can't change its rule, the schedule runs on {}", self.walkers[self.kind].name, TURMITE);
+                                let show_menu = self.show_menu;
+                                self.switch(turmite, &mut sim, ctx);
+                                self.show_menu = show_menu;
+                            },
+                            None => eprintln!("{} can't change its rule, the schedule waits for another walker", self.walkers[self.kind].name),
+                        }
+                    }
+                },
+                Err(e) => eprintln!("schedule: {}", e),
+            },
+            Action::Rules(line) => match RuleMap::parse(&line) {
+                Ok(map) => {
+                    self.rule_map = Some(map);
+                    let (kind, show_menu) = (self.kind, self.show_menu);
+                    self.switch(kind, &mut sim, ctx);
+                    self.show_menu = show_menu;
+                    if let Err(e) = &self.mapped {
+                        eprintln!("{} can't follow this rule map: {}", self.walkers[self.kind].name, e);
+                    }
+                },
+                Err(e) => eprintln!("rules: {}", e),
+            },
+            Action::Zoom(factor, x, y) => sim.walker.zoom(factor, (x, y), ctx),
+            Action::Pan(dx, dy) => sim.walker.pan(dx, dy),
+            Action::Toggle(x, y) => sim.walker.toggle(x, y),
+            Action::Place(x, y) => sim.walker.place(x, y),
+            Action::Resize(width, height) => {
+                // при проигрывании окно подгоняется под записанное
+                if self.replay.is_some() && (width, height) != self.resolution {
+                    graphics::set_drawable_size(ctx, width, height).unwrap_or_else(|e| eprintln!("{}", e));
+                }
+                // иначе ggez растягивает старую картинку на новое окно
+                graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height)).unwrap();
+
+                self.resolution = (width, height);
+                sim.walker.resize(self.resolution, ctx);
+            },
+            Action::Export => self.export(&sim),
+        }
+
+        drop(sim);
+        // вдруг рабочий поток спит в паузе, а его только что запустили
+        shared.1.notify_all();
+    }
+
+    // следующее записанное действие, если до него уже дошли
+    fn next_replayed(&mut self, step: u64) -> Option<Action> {
+        let replay = self.replay.as_mut()?;
+        match replay.actions.front() {
+            Some(&(at, _)) if at <= step => replay.actions.pop_front().map(|(_, action)| action),
+            _ => None,
+        }
+    }
+
+    // новый ходок начинает с чистой доски и нулевого шага
+    fn switch(&mut self, kind: usize, sim: &mut Sim, ctx: &mut Context) {
+        self.kind = kind;
+        sim.walker = (self.walkers[kind].make)(self.resolution, self.dim, &self.themes[self.theme], ctx);
+        // правило первой фазы ставим до доски: картинки делятся на столько цветов, сколько у него
+        sim.phase = 0;
+        sim.apply_phase();
+        sim.walker.set_up(&self.setup);
+        self.mapped = match &self.rule_map {
+            Some(map) => sim.walker.set_rule_map(map),
+            None => Ok(()),
+        };
+        sim.walker.set_render_mode(self.render_mode);
+
+        sim.mode = Mode::StepByStep;
+        sim.step = 0;
+        sim.target = None;
+        sim.stalled = false;
+        sim.symmetry.clear();
+        sim.symmetry_log.clear();
+        sim.start_schedule();
+        self.rate_window = (timer::duration_to_f64(timer::time_since_start(ctx)), 0);
+        let log_log = self.plots.log_log;
+        self.plots = Plots::new();
+        self.plots.log_log = log_log;
+        self.show_menu = false;
+    }
+
+    fn hud(&self, ctx: &Context, sim: &Sim) -> String {
+        let stats = sim.walker.stats();
+        let mode = match sim.mode {
+            Mode::Stream(speed) => format!("stream, {}", speed.label()),
+            Mode::StepByStep => format!("paused, {}", self.speed.label()),
+        };
+
+        let mut lines = vec!(
+            format!("{}, step {}, seed {}", self.walkers[self.kind].name, sim.step, self.setup.seed),
+            format!("theme {}, showing {}", self.themes[self.theme].name, self.render_mode.label()),
+            format!("{:.0} steps/s, {:.0} fps", self.steps_per_second, timer::fps(ctx)),
+            mode,
+            format!("at ({}, {}) facing {}", stats.coord.0, stats.coord.1, stats.heading),
+            format!("{}: {}", stats.population_label, stats.population),
+            format!("explored x {}..{}, y {}..{}",
+                stats.explored.min.0, stats.explored.max.0, stats.explored.min.1, stats.explored.max.1),
+        );
+        if let Some(target) = sim.target {
+            lines.push(format!("running until {}", target));
+        }
+        if sim.stalled {
+            lines.push(String::from("the walker can't move, its rule gave no step"));
+        }
+        if sim.step > 0 && !sim.walker.can_step_back() {
+            lines.push(String::from("step back is off: the history ran out"));
+        }
+        if let Some(schedule) = &sim.schedule {
+            let phase = &schedule.phases[sim.phase];
+            lines.push(if sim.ruled {
+                format!("rule {}, phase {} of {}", phase.rule, sim.phase + 1, schedule.phases.len())
+            } else {
+                String::from("this walker ignores the rule schedule")
+            });
+            if let (Some(until), Some(next)) = (phase.until, schedule.phases.get(sim.phase + 1)) {
+                lines.push(format!("  until {}, then {}", until.label(), next.rule));
+            }
+        }
+        if let Some(map) = &self.rule_map {
+            lines.push(match &self.mapped {
+                Ok(()) => format!("rule map: {} regions, here {}", map.regions.len(), map.rule_at(stats.coord).unwrap_or("own rule")),
+                Err(e) => format!("rule map is off: {}", e),
+            });
+        }
+        if sim.watch != Watch::Off {
+            lines.push(format!("symmetry ({}): {}", sim.watch.label(), symmetry_label(&sim.symmetry)));
+            // последние перемены, свежая внизу
+            for (at, symmetry) in sim.symmetry_log.iter().rev().take(3).rev() {
+                lines.push(format!("  step {}: {}", at, symmetry_label(symmetry)));
+            }
+        }
+        if let Some((path, _)) = &self.recording {
+            lines.push(format!("recording to {}", path));
+        }
+        if let Some(replay) = &self.replay {
+            lines.push(format!("replaying {}, {} actions left", replay.path, replay.actions.len()));
+        }
+        if let Some(exported) = &self.exported {
+            lines.push(exported.clone());
+        }
+        if !self.target_input.is_empty() {
+            lines.push(format!("run until: {}_", self.target_input));
+        }
+
+        lines.join("\n")
+    }
+
+    fn measure_rate(&mut self, ctx: &Context, step: u64) {
+        let now = timer::duration_to_f64(timer::time_since_start(ctx));
+        let (since, from) = self.rate_window;
+        if now - since >= 0.5 {
+            self.steps_per_second = (step as f64 - from as f64).max(0.0) / (now - since);
+            self.rate_window = (now, step);
+        }
+    }
+
+    fn draw_hud(&mut self, ctx: &mut Context, hud: String) -> GameResult {
+        const PADDING: f32 = 6.0;
+
+        let text = graphics::Text::new(hud);
+        let graphics::Rect { w, h, .. } = text.dimensions(ctx);
+        // панель перерисовывается каждый кадр поверх доски и не сжимается,
+        // иначе от длинной строки останется хвост
+        self.panel_size = (
+            self.panel_size.0.max(w + 2.0 * PADDING),
+            self.panel_size.1.max(h + 2.0 * PADDING),
+        );
+
+        let panel = graphics::Mesh::new_rectangle(
+            ctx,
+            DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, self.panel_size.0, self.panel_size.1),
+            [0.0, 0.0, 0.0, 0.8].into(),
+        )?;
+        graphics::draw(ctx, &panel, graphics::DrawParam::default())?;
+        graphics::draw(ctx, &text, graphics::DrawParam::default()
+        .dest(na::Point2::new(PADDING, PADDING)))?;
+
+        Ok(())
+    }
+
+    fn draw_menu(&mut self, ctx: &mut Context) -> GameResult {
+        const PADDING: f32 = 12.0;
+
+        let mut lines = vec!(String::from("choose a walker (M to close):"));
+        for (k, kind) in self.walkers.iter().enumerate().take(9) {
+            let current = if k == self.kind { " *" } else { "" };
+            lines.push(format!("{}. {}{}", k + 1, kind.name, current));
         }
+
+        let text = graphics::Text::new(lines.join("\n"));
+        let graphics::Rect { w, h, .. } = text.dimensions(ctx);
+        let (w, h) = (w + 2.0 * PADDING, h + 2.0 * PADDING);
+        let corner = ((self.resolution.0 - w) / 2.0, (self.resolution.1 - h) / 2.0);
+
+        let panel = graphics::Mesh::new_rectangle(
+            ctx,
+            DrawMode::fill(),
+            graphics::Rect::new(corner.0, corner.1, w, h),
+            [0.0, 0.0, 0.0, 0.9].into(),
+        )?;
+        graphics::draw(ctx, &panel, graphics::DrawParam::default())?;
+        graphics::draw(ctx, &text, graphics::DrawParam::default()
+        .dest(na::Point2::new(corner.0 + PADDING, corner.1 + PADDING)))?;
+
+        Ok(())
     }
 }
 
-impl<W: Walker> ggez::event::EventHandler for State<W> {
+// дрожание руки при клике не должно превращаться в перетаскивание
+const CLICK_SLOP: f32 = 4.0;
+
+impl ggez::event::EventHandler<GameError> for State {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
         const DESIRED_FPS: u32 = 30;
 
         while timer::check_update_time(ctx, DESIRED_FPS) {
-            if !keyboard::is_key_pressed(ctx, KeyCode::Space) && self.buttons.space {
-                self.buttons.space = false;
+            let streaming = match self.worker.lock().mode {
+                Mode::Stream(_) => true,
+                Mode::StepByStep => false,
+            };
+
+            let mut actions = vec!();
+            for command in self.input.poll(ctx) {
+                match command {
+                    Command::ToggleStream => actions.push(if streaming { Action::Pause } else { Action::Play }),
+                    Command::Step => if !streaming {
+                        actions.push(Action::Step);
+                    },
+                    Command::StepBack => if !streaming {
+                        actions.push(Action::StepBack);
+                    },
+                    Command::Faster => actions.push(Action::Speed(self.speed.faster().0)),
+                    Command::Slower => actions.push(Action::Speed(self.speed.slower().0)),
+                    Command::RunUntil => {
+                        actions.push(Action::RunUntil(self.target_input.parse().unwrap_or(0)));
+                        self.target_input.clear();
+                    },
+                    Command::EraseDigit => {
+                        self.target_input.pop();
+                    },
+                    Command::Follow => actions.push(Action::Follow),
+                    Command::Theme => actions.push(Action::Theme),
+                    Command::Render => actions.push(Action::Render),
+                    Command::Hud => actions.push(Action::Hud),
+                    Command::Plots => actions.push(Action::Plots),
+                    Command::LogLog => actions.push(Action::LogLog),
+                    Command::Menu => actions.push(Action::Menu),
+                    Command::Symmetry => actions.push(Action::Symmetry),
+                    Command::Export => actions.push(Action::Export),
+                }
             }
 
-            if !keyboard::is_key_pressed(ctx, KeyCode::Right) && self.buttons.right {
-                self.buttons.right = false;
+            // при проигрывании вместо клавиатуры — очередное действие из файла
+            if self.replay.is_some() {
+                let step = self.worker.lock().step;
+                actions = self.next_replayed(step).into_iter().collect();
+            }
+            for action in actions {
+                self.apply(action, ctx);
             }
 
-            if !keyboard::is_key_pressed(ctx, KeyCode::Left) && self.buttons.left {
-                self.buttons.left = false;
+            let finished = self.replay.as_ref().is_some_and(|replay| replay.actions.is_empty());
+            if finished {
+                self.replay = None;
             }
+            // записанное действие должно случиться ровно на своём шаге
+            let hold_at = self.replay.as_ref().and_then(|replay| replay.actions.front()).map(|&(step, _)| step);
+            self.worker.lock().hold_at = hold_at;
+        }
 
-            match self.mode {
-                Mode::Stream(steps_per_frame) => {
-                    for _ in 0..steps_per_frame {
-                        self.walker.step();
-                    }
+        let (step, stats) = {
+            let sim = self.worker.lock();
+            (sim.step, sim.walker.stats())
+        };
+        self.measure_rate(ctx, step);
+        self.plots.record(step, &stats);
 
-                    if keyboard::is_key_pressed(ctx, KeyCode::Space) && !self.buttons.space {
-                        self.buttons.space = true;
-                        self.mode = Mode::StepByStep;
-                    }
-                },
-                Mode::StepByStep => {
-                    if keyboard::is_key_pressed(ctx, KeyCode::Right) && !self.buttons.right {
-                        self.buttons.right = true;
-                        self.walker.step();
-                    }
+        Ok(())
+    }
 
-                    if keyboard::is_key_pressed(ctx, KeyCode::Left) && !self.buttons.left {
-                        self.buttons.left = true;
-                        self.walker.step_back();
-                    }
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        // доска и цифры на панели берутся из одного и того же шага,
+        // а рисуем уже отпустив симуляцию
+        let (frame, hud, label) = {
+            let mut sim = self.worker.lock();
+            sim.walker.recycle(std::mem::take(&mut self.spent));
+            (sim.walker.frame(), self.hud(ctx, &sim), sim.walker.stats().population_label)
+        };
+        self.spent = frame.draw(ctx, &mut self.chunk_images)?;
 
-                    if keyboard::is_key_pressed(ctx, KeyCode::Space) && !self.buttons.space {
-                        self.buttons.space = true;
-                        self.mode = Mode::Stream(10);
-                    }
+        if self.show_hud {
+            self.draw_hud(ctx, hud)?;
+        }
+        if self.show_plots {
+            let (w, h) = self.resolution;
+            let width = (w / 3.0).min(420.0);
+            self.plots.draw(ctx, label, graphics::Rect::new(w - width, 0.0, width, h))?;
+        }
+        if self.show_menu {
+            self.draw_menu(ctx)?;
+        }
+
+        graphics::present(ctx)?;
+        Ok(())
+    }
+
+    fn text_input_event(&mut self, ctx: &mut Context, ch: char) {
+        if self.replay.is_some() {
+            return;
+        }
+
+        // пока открыто меню, цифра выбирает ходока
+        if self.show_menu {
+            if let Some(k) = ch.to_digit(10) {
+                if k >= 1 && (k as usize) <= self.walkers.len() {
+                    let name = self.walkers[k as usize - 1].name.clone();
+                    self.apply(Action::Switch(name), ctx);
                 }
             }
+            return;
         }
 
-        Ok(())
+        if ch.is_ascii_digit() && self.target_input.len() < 18 {
+            self.target_input.push(ch);
+        }
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        self.walker.draw(ctx);
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: f32, y: f32) {
+        if self.replay.is_some() {
+            return;
+        }
 
-        Ok(())
+        let cursor = mouse::position(ctx);
+        self.apply(Action::Zoom(1.25f32.powf(y), cursor.x, cursor.y), ctx);
+    }
+
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, _button: MouseButton, _x: f32, _y: f32) {
+        self.dragged = 0.0;
+    }
+
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        if self.dragged >= CLICK_SLOP || self.replay.is_some() {
+            return;
+        }
+
+        match button {
+            MouseButton::Left => self.apply(Action::Toggle(x, y), ctx),
+            MouseButton::Right => self.apply(Action::Place(x, y), ctx),
+            _ => {},
+        }
+    }
+
+    fn mouse_motion_event(&mut self, ctx: &mut Context, _x: f32, _y: f32, dx: f32, dy: f32) {
+        if self.replay.is_some() {
+            return;
+        }
+
+        if mouse::button_pressed(ctx, MouseButton::Left) || mouse::button_pressed(ctx, MouseButton::Middle) {
+            self.dragged += dx.abs() + dy.abs();
+            if self.dragged >= CLICK_SLOP {
+                self.apply(Action::Pan(dx, dy), ctx);
+            }
+        }
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        // свёрнутое окно
+        if width < 1.0 || height < 1.0 {
+            return;
+        }
+
+        self.apply(Action::Resize(width, height), ctx);
     }
 }
@@ -1,36 +1,148 @@
+// lib.rs — корень библиотеки, но бинарник собирает его ещё раз своим модулем,
+// чтобы остальные модули ходили в него как crate::lib
+#![allow(special_module_name)]
+
 use ggez::*;
 use ggez::conf::{FullscreenType};
 
 pub mod lib;
 pub mod ant;
 pub mod engineer;
+pub mod theme;
+pub mod keys;
+pub mod noise;
+pub mod sensing;
+pub mod script;
+pub mod plugin;
+pub mod sweep;
 
 use lib::*;
 use crate::ant::*;
 use crate::engineer::*;
+use crate::script::*;
 
 // типаж, который рисует доску с муравьём
 // Новад доска с новыми правилами
 
 pub fn main() {
-    // hardcoded for now TODO: get it from somewhere!
-    let screen_resolution = (1920.0, 1080.0);
+    // sweep перебирает правила без окна и пишет отчёт
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("sweep") {
+        sweep::run(&args[1..]);
+        return;
+    }
+
+    // начальный размер окна, дальше его можно менять мышкой
+    let screen_resolution = (1280.0, 720.0);
     let short_dim = 20;
 
     let mut c = conf::Conf::new();
     let mut window_mode = c.window_mode;
 
-    let (w, h) = (screen_resolution.0, screen_resolution.1);;
+    let (w, h) = (screen_resolution.0, screen_resolution.1);
     window_mode.width = w;
     window_mode.height = h;
-    window_mode.fullscreen_type = FullscreenType::True;
+    window_mode.fullscreen_type = FullscreenType::Windowed;
+    window_mode.resizable = true;
     c = c.window_mode(window_mode);
 
-    let (ref mut ctx, ref mut event_loop) = ContextBuilder::new("Ant", "Tomarchelone")
-        .conf(c).build().unwrap();
+    let (mut context, event_loop) = ContextBuilder::new("Ant", "Tomarchelone")
+        .default_conf(c).build().unwrap();
+    let ctx = &mut context;
     graphics::set_window_title(ctx, "Ant");
-    let state = &mut State::<EngineerWalker>::new(ctx, short_dim);
+    let themes = theme::load("themes.cfg");
+
+    let mut walkers = vec!(
+        WalkerKind::of::<AntWalker>("ant"),
+        WalkerKind::of::<EngineerWalker>("engineer"),
+    );
+    // муравей с шумом из noise.cfg
+    let noise = noise::load("noise.cfg");
+    walkers.push(WalkerKind {
+        name: String::from("noisy ant"),
+        make: Box::new(move |resolution, dim, theme, ctx| Box::new(AntWalker::noisy(noise.clone(), resolution, dim, theme, ctx))),
+    });
+    // тьюрмит с правилом-строчкой, её меняет расписание
+    walkers.push(WalkerKind {
+        name: String::from(TURMITE),
+        make: Box::new(|resolution, dim, theme, ctx| {
+            let rule = Box::new(Turmite::parse("RL").unwrap());
+            Box::new(ScriptWalker::with_rule(rule, resolution, dim, theme, ctx))
+        }),
+    });
+    // муравей, который смотрит на соседей, с правилом из sensing.cfg
+    let sensing = sensing::load("sensing.cfg");
+    walkers.push(WalkerKind {
+        name: String::from("sensing ant"),
+        make: Box::new(move |resolution, dim, theme, ctx| Box::new(AntWalker::sensing(sensing.clone(), resolution, dim, theme, ctx))),
+    });
+    // каждый скрипт рядом с программой — отдельный ходок со своим правилом
+    let mut scripts: Vec<String> = std::fs::read_dir(".")
+        .map(|dir| dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".rhai"))
+            .collect())
+        .unwrap_or_default();
+    scripts.sort();
+    for path in scripts {
+        walkers.push(WalkerKind {
+            name: path.clone(),
+            make: Box::new(move |resolution, dim, theme, ctx| Box::new(ScriptWalker::load(&path, resolution, dim, theme, ctx))),
+        });
+    }
+    walkers.extend(plugin::load(plugin::PLUGINS));
+
+    // ходока можно назвать аргументом, иначе он выбирается в меню;
+    // --record файл пишет сессию, --replay файл проигрывает записанную,
+    // --setup файл задаёт начальную доску, а --seed N меняет в ней зерно,
+    // --export png|pgm|pbm — в каком формате клавиша сохраняет доску,
+    // --schedule файл — расписание правил для ходоков, которые понимают строчки вроде LLRR,
+    // --rules файл — карта, где у каждой области доски своё правило
+    let mut kind = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut setup = None;
+    let mut seed = None;
+    let mut export = None;
+    let mut schedule = None;
+    let mut rule_map = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record = args.next(),
+            "--replay" => replay = args.next(),
+            "--setup" => setup = args.next().map(|path| Setup::load(&path)),
+            "--seed" => seed = args.next().and_then(|n| n.parse().ok()),
+            "--export" => export = args.next(),
+            "--schedule" => schedule = args.next().and_then(|path| Schedule::load(&path)),
+            "--rules" => rule_map = args.next().and_then(|path| RuleMap::load(&path)),
+            name => kind = walkers.iter().position(|kind| kind.name == name),
+        }
+    }
+
+    let mut state = State::new(ctx, short_dim, themes, keys::load("keys.cfg"), walkers, kind);
+    if setup.is_some() || seed.is_some() {
+        let mut setup = setup.unwrap_or_else(Setup::new);
+        setup.seed = seed.unwrap_or(setup.seed);
+        state.set_up(setup, ctx);
+    }
+    if let Some(schedule) = schedule {
+        state.schedule(schedule, ctx);
+    }
+    if let Some(map) = rule_map {
+        state.map_rules(map, ctx);
+    }
+    if let Some(format) = export {
+        state.export_as(&format);
+    }
+    if let Some(path) = record {
+        state.record_to(&path);
+    }
+    if let Some(path) = replay {
+        state.replay_from(&path);
+    }
 
-    event::run(ctx, event_loop, state).unwrap();
+    event::run(context, event_loop, state);
 
 }
@@ -0,0 +1,57 @@
+use std::fs;
+
+use crate::ant::Noise;
+use crate::lib::{config_lines, key_value};
+
+// Файл с шумом для муравья выглядит так:
+//
+//   white = 0 0 0.98 0.02
+//   black = 0.98 0 0.02 0
+//   flip_failure = 0.001
+//
+// white и black — веса поворотов налево, прямо, направо и назад,
+// flip_failure — вероятность, что клетка не перекрасится;
+// чего нет в файле, остаётся как у обычного муравья
+pub fn load(path: &str) -> Noise {
+    let mut noise = Noise::none();
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return noise,
+    };
+
+    for (n, line) in config_lines(&text) {
+        if let Err(e) = set(&mut noise, line) {
+            eprintln!("{}:{}: {}", path, n, e);
+        }
+    }
+
+    noise
+}
+
+fn set(noise: &mut Noise, line: &str) -> Result<(), String> {
+    let (key, value) = key_value(line)?;
+    let numbers: Vec<f64> = value.numbers()?;
+
+    let weights = || {
+        if numbers.len() != 4 {
+            return Err(format!("`{}` needs 4 weights: left, straight, right, back", key));
+        }
+        if numbers.iter().any(|&w| w < 0.0) || numbers.iter().sum::<f64>() <= 0.0 {
+            return Err(format!("`{}` needs non-negative weights that are not all zero", key));
+        }
+        Ok([numbers[0], numbers[1], numbers[2], numbers[3]])
+    };
+
+    match key {
+        "white" => noise.turns[0] = weights()?,
+        "black" => noise.turns[1] = weights()?,
+        "flip_failure" => match numbers.as_slice() {
+            &[p] if (0.0..=1.0).contains(&p) => noise.flip_failure = p,
+            _ => return Err(format!("`{}` needs one probability", key)),
+        },
+        _ => return Err(format!("unknown key `{}`", key)),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use ggez::*;
+use nalgebra as na;
+use ggez::graphics::{self, DrawMode};
+
+use super::*;
+
+// больше точек в ряду не храним
+const PLOT_SAMPLES: usize = 512;
+
+// значения метрики по шагам; когда точки кончаются, выкидываем каждую вторую
+// и пишем вдвое реже, так что в ряд всегда влезает весь прогон
+pub struct Series {
+    points: Vec<(f64, f64)>,
+    every: u64,
+    next: u64,
+}
+
+impl Default for Series {
+    fn default() -> Series {
+        Series::new()
+    }
+}
+
+impl Series {
+    pub fn new() -> Series {
+        Series {
+            points: vec!(),
+            every: 1,
+            next: 0,
+        }
+    }
+
+    pub fn record(&mut self, step: u64, value: f64) {
+        // после шагов назад будущее уже не наступит
+        if self.points.last().is_some_and(|&(x, _)| x > step as f64) {
+            self.points.retain(|&(x, _)| x <= step as f64);
+            self.next = step;
+        }
+
+        if step < self.next {
+            return;
+        }
+
+        self.points.push((step as f64, value));
+        self.next = step + self.every;
+
+        if self.points.len() >= PLOT_SAMPLES {
+            let mut k = 0;
+            self.points.retain(|_| {
+                k += 1;
+                k % 2 == 1
+            });
+            self.every *= 2;
+        }
+    }
+
+    fn draw(&self, ctx: &mut Context, name: &str, rect: graphics::Rect, log_log: bool) -> GameResult {
+        let frame = graphics::Mesh::new_rectangle(ctx, DrawMode::stroke(1.0), rect, [0.6, 0.6, 0.6, 1.0].into())?;
+        graphics::draw(ctx, &frame, graphics::DrawParam::default())?;
+
+        let last = self.points.last().map_or(0.0, |&(_, y)| y);
+        let label = graphics::Text::new(format!("{}: {:.0}", name, last));
+        graphics::draw(ctx, &label, graphics::DrawParam::default()
+        .dest(na::Point2::new(rect.x + 4.0, rect.y + 2.0)))?;
+
+        let scale = |v: f64| if log_log { v.max(1.0).log10() } else { v };
+        let points: Vec<(f64, f64)> = self.points.iter().map(|&(x, y)| (scale(x), scale(y))).collect();
+        if points.len() < 2 {
+            return Ok(());
+        }
+
+        let (min_x, max_x) = (points[0].0, points[points.len() - 1].0);
+        let min_y = points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+        let max_y = points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+        let sx = if max_x > min_x { rect.w as f64 / (max_x - min_x) } else { 0.0 };
+        let sy = if max_y > min_y { rect.h as f64 / (max_y - min_y) } else { 0.0 };
+
+        let mut line: Vec<na::Point2<f32>> = points.iter()
+        .map(|&(x, y)| na::Point2::new(
+            rect.x + ((x - min_x) * sx) as f32,
+            rect.y + rect.h - ((y - min_y) * sy) as f32,
+        ))
+        .collect();
+        line.dedup();
+        if line.len() < 2 {
+            return Ok(());
+        }
+
+        let mesh = graphics::MeshBuilder::new()
+        .line(&line, 2.0, [0.9, 0.6, 0.1, 1.0].into())?
+        .build(ctx)?;
+        graphics::draw(ctx, &mesh, graphics::DrawParam::default())?;
+
+        Ok(())
+    }
+}
+
+pub struct Plots {
+    population: Series,
+    distance: Series,
+    area: Series,
+    pub log_log: bool,
+}
+
+impl Default for Plots {
+    fn default() -> Plots {
+        Plots::new()
+    }
+}
+
+impl Plots {
+    pub fn new() -> Plots {
+        Plots {
+            population: Series::new(),
+            distance: Series::new(),
+            area: Series::new(),
+            log_log: false,
+        }
+    }
+
+    pub fn record(&mut self, step: u64, stats: &Stats) {
+        let (x, y) = (stats.coord.0 as f64, stats.coord.1 as f64);
+        self.population.record(step, stats.population as f64);
+        self.distance.record(step, (x * x + y * y).sqrt());
+        self.area.record(step, stats.explored.area() as f64);
+    }
+
+    pub fn draw(&self, ctx: &mut Context, population_label: &str, rect: graphics::Rect) -> GameResult {
+        const PADDING: f32 = 8.0;
+
+        let panel = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), rect, [0.0, 0.0, 0.0, 0.8].into())?;
+        graphics::draw(ctx, &panel, graphics::DrawParam::default())?;
+
+        let axes = graphics::Text::new(if self.log_log { "log-log, x = step" } else { "linear, x = step" });
+        graphics::draw(ctx, &axes, graphics::DrawParam::default()
+        .dest(na::Point2::new(rect.x + PADDING, rect.y + PADDING)))?;
+
+        let top = rect.y + 2.0 * PADDING + 16.0;
+        let h = (rect.h - (top - rect.y) - 3.0 * PADDING) / 3.0;
+        let series = [
+            (&self.population, population_label),
+            (&self.distance, "distance from origin"),
+            (&self.area, "explored area"),
+        ];
+        for (k, (series, name)) in series.iter().enumerate() {
+            let chart = graphics::Rect::new(rect.x + PADDING, top + k as f32 * (h + PADDING), rect.w - 2.0 * PADDING, h);
+            series.draw(ctx, name, chart, self.log_log)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::lib::*;
+use crate::script::*;
+
+// папка, из которой подгружаются сторонние ходоки
+pub const PLUGINS: &str = "plugins";
+
+// версия интерфейса; при несовпадении плагин не грузится.
+// во второй версии walker_step получил координаты ходока
+pub const ABI_VERSION: u32 = 2;
+
+// Плагин — cdylib с такими функциями (extern "C", без искажения имён):
+//
+//   uint32_t    walker_abi_version(void);        // вернуть ABI_VERSION
+//   const char *walker_name(void);               // имя для меню, строка живёт вечно
+//   void       *walker_create(void);             // состояние одного ходока, NULL — ошибка
+//   int32_t     walker_step(void *state, int64_t cell, int64_t x, int64_t y, int64_t heading,
+//                           WalkerMove *out);
+//   void        walker_destroy(void *state);
+//
+// walker_step получает то же, что step в скрипте: состояние клетки, координаты ходока
+// и направление (0 вверх, 1 вправо, 2 вниз, 3 влево), заполняет out и возвращает 0,
+// а любое другое число считается ошибкой, и ходок останавливается.
+// Если walker_create вернул NULL, ходок стоит, а walker_destroy для него не зовётся.
+// Доску, отрисовку и шаг назад берёт на себя программа.
+// walker_create зовётся из потока окна, walker_step — из потока симуляции,
+// walker_destroy — из любого из них, но для одного состояния никогда из двух потоков сразу.
+// Поэтому состояние не должно держаться за поток, который его создал:
+// никаких thread-local переменных и объектов, привязанных к потоку.
+#[repr(C)]
+pub struct WalkerMove {
+    pub cell: i64,
+    pub turn: i64,
+    // 0 — остаться на месте, иначе шагнуть вперёд
+    pub forward: u8,
+}
+
+struct Library {
+    name: String,
+    create: unsafe extern "C" fn() -> *mut c_void,
+    step: unsafe extern "C" fn(*mut c_void, i64, i64, i64, i64, *mut WalkerMove) -> i32,
+    destroy: unsafe extern "C" fn(*mut c_void),
+    // указатели выше живы, пока библиотека загружена, так что она идёт последней
+    _library: libloading::Library,
+}
+
+impl Library {
+    fn open(path: &Path) -> Result<Library, String> {
+        unsafe {
+            let library = libloading::Library::new(path).map_err(|e| e.to_string())?;
+
+            let version = *library.get::<unsafe extern "C" fn() -> u32>(b"walker_abi_version")
+                .map_err(|e| e.to_string())?;
+            let version = version();
+            if version != ABI_VERSION {
+                return Err(format!("ABI version {}, expected {}", version, ABI_VERSION));
+            }
+
+            let name = *library.get::<unsafe extern "C" fn() -> *const c_char>(b"walker_name")
+                .map_err(|e| e.to_string())?;
+            let name = name();
+            if name.is_null() {
+                return Err(String::from("walker_name returned null"));
+            }
+            let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+            Ok(Library {
+                name,
+                create: *library.get(b"walker_create").map_err(|e| e.to_string())?,
+                step: *library.get(b"walker_step").map_err(|e| e.to_string())?,
+                destroy: *library.get(b"walker_destroy").map_err(|e| e.to_string())?,
+                _library: library,
+            })
+        }
+    }
+}
+
+// правило, которое считает чужая библиотека
+struct Plugin {
+    library: Arc<Library>,
+    state: *mut c_void,
+    failed: bool,
+}
+
+// Send не выводится только из-за сырого указателя state. Переносить его можно:
+// Plugin живёт внутри ходока, а ходок — под замком симуляции, так что state трогает
+// один поток за раз, и по ABI выше плагин обещает не привязывать state к потоку.
+// Sync не нужен и не объявлен: общего доступа к state из двух потоков нет.
+unsafe impl Send for Plugin {}
+
+impl Plugin {
+    fn new(library: Arc<Library>) -> Plugin {
+        let state = unsafe { (library.create)() };
+        if state.is_null() {
+            eprintln!("{}: walker_create returned null", library.name);
+        }
+        Plugin {
+            failed: state.is_null(),
+            library,
+            state,
+        }
+    }
+}
+
+impl Rule for Plugin {
+    fn call(&mut self, cell: i64, x: i64, y: i64, heading: i64) -> Option<Move> {
+        if self.failed {
+            return None;
+        }
+
+        let mut out = WalkerMove {
+            cell,
+            turn: 0,
+            forward: 1,
+        };
+        let code = unsafe { (self.library.step)(self.state, cell, x, y, heading, &mut out) };
+        if code != 0 {
+            eprintln!("{}: walker_step returned {}", self.library.name, code);
+            self.failed = true;
+            return None;
+        }
+
+        Some(Move {
+            cell: out.cell,
+            turn: out.turn,
+            forward: out.forward != 0,
+        })
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        // состояния так и не было, освобождать нечего
+        if self.state.is_null() {
+            return;
+        }
+        unsafe { (self.library.destroy)(self.state) };
+    }
+}
+
+// по ходоку на каждую библиотеку в папке; сломанные пропускаем с сообщением
+pub fn load(dir: &str) -> Vec<WalkerKind> {
+    let mut paths: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION))
+            .collect(),
+        // папки нет — значит, и плагинов нет
+        Err(_) => return vec!(),
+    };
+    paths.sort();
+
+    let mut kinds = vec!();
+    for path in paths {
+        let library = match Library::open(&path) {
+            Ok(library) => Arc::new(library),
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                continue;
+            },
+        };
+
+        kinds.push(WalkerKind {
+            name: library.name.clone(),
+            make: Box::new(move |resolution, dim, theme, ctx| {
+                let rule = Box::new(Plugin::new(library.clone()));
+                Box::new(ScriptWalker::with_rule(rule, resolution, dim, theme, ctx))
+            }),
+        });
+    }
+
+    kinds
+}
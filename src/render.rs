@@ -0,0 +1,646 @@
+use ggez::*;
+use std::collections::{HashMap, HashSet};
+use nalgebra as na;
+use ggez::graphics::{self, Mesh, MeshBatch};
+
+use super::Theme;
+
+// размеры клетки в пикселях, между которыми можно зумить
+pub const MIN_CELL_SIZE: f32 = 2.0;
+pub const MAX_CELL_SIZE: f32 = 240.0;
+
+pub struct Camera {
+    pub resolution: (f32, f32),
+    pub dim: (i64, i64),
+    pub cell_size: f32,
+    pub center_coord: (i64, i64),
+    pub follow: bool,
+    // сдвиг мышью, который ещё не набрал целую клетку
+    drag: (f32, f32),
+}
+
+impl Camera {
+    pub fn new(resolution: (f32, f32), cell_size: f32, center_coord: (i64, i64)) -> Camera {
+        Camera {
+            resolution,
+            dim: Camera::dim_for(resolution, cell_size),
+            cell_size,
+            center_coord,
+            follow: true,
+            drag: (0.0, 0.0),
+        }
+    }
+
+    fn dim_for(resolution: (f32, f32), cell_size: f32) -> (i64, i64) {
+        ((resolution.0 / cell_size).ceil() as i64, (resolution.1 / cell_size).ceil() as i64)
+    }
+
+    pub fn board_to_screen(&self, board_i: i64, board_j: i64) -> (i64, i64) {
+        // кординаты центра доски с точки зрения верхнего левого угла
+        let (cx, cy) = (self.dim.0 / 2, self.dim.1 / 2);
+        // координаты центра с точки зрения (0, 0) доски
+        let (cent_x, cent_y) = self.center_coord;
+        (board_i + cx - cent_x, board_j + cy - cent_y)
+    }
+
+    pub fn screen_to_board(&self, screen_i: i64, screen_j: i64) -> (i64, i64) {
+        let (cx, cy) = (self.dim.0 / 2, self.dim.1 / 2);
+        let (cent_x, cent_y) = self.center_coord;
+        (screen_i - cx + cent_x, screen_j - cy + cent_y)
+    }
+
+    pub fn pixel_to_screen(&self, x: f32, y: f32) -> (i64, i64) {
+        ((x / self.cell_size).floor() as i64, (y / self.cell_size).floor() as i64)
+    }
+
+    pub fn resize(&mut self, resolution: (f32, f32)) {
+        self.resolution = resolution;
+        self.dim = Camera::dim_for(resolution, self.cell_size);
+    }
+
+    // true, если масштаб поменялся и всё надо перерисовать
+    pub fn zoom(&mut self, factor: f32, around: (f32, f32)) -> bool {
+        let cell_size = (self.cell_size * factor).clamp(MIN_CELL_SIZE, MAX_CELL_SIZE);
+        if (cell_size - self.cell_size).abs() < 0.01 {
+            return false;
+        }
+
+        // клетка под курсором остаётся под курсором
+        let (ai, aj) = self.pixel_to_screen(around.0, around.1);
+        let anchor = self.screen_to_board(ai, aj);
+
+        self.cell_size = cell_size;
+        self.dim = Camera::dim_for(self.resolution, cell_size);
+
+        let (ai, aj) = self.pixel_to_screen(around.0, around.1);
+        self.center_coord = (anchor.0 + self.dim.0 / 2 - ai, anchor.1 + self.dim.1 / 2 - aj);
+        self.drag = (0.0, 0.0);
+
+        true
+    }
+
+    // true, если картинка сдвинулась хотя бы на клетку
+    pub fn pan(&mut self, dx: f32, dy: f32) -> bool {
+        self.follow = false;
+        self.drag.0 += dx;
+        self.drag.1 += dy;
+
+        let shift = ((self.drag.0 / self.cell_size) as i64, (self.drag.1 / self.cell_size) as i64);
+        if shift == (0, 0) {
+            return false;
+        }
+
+        self.drag.0 -= shift.0 as f32 * self.cell_size;
+        self.drag.1 -= shift.1 as f32 * self.cell_size;
+        self.center_coord.0 -= shift.0;
+        self.center_coord.1 -= shift.1;
+
+        true
+    }
+
+    pub fn toggle_follow(&mut self, coord: (i64, i64)) -> bool {
+        self.follow = !self.follow;
+        if self.follow {
+            self.center_coord = coord;
+            self.drag = (0.0, 0.0);
+        }
+
+        self.follow
+    }
+
+    // в режиме слежения переезжаем к ходоку, когда он уходит с экрана
+    pub fn track(&mut self, coord: (i64, i64)) -> bool {
+        if !self.follow {
+            return false;
+        }
+
+        let (i, j) = self.board_to_screen(coord.0, coord.1);
+        if i < 0 || j < 0 || i >= self.dim.0 || j >= self.dim.1 {
+            self.center_coord = coord;
+            return true;
+        }
+
+        false
+    }
+}
+
+// когда клетка меньше этого, рисуем доску кусками-картинками, пиксель на клетку
+pub const LOD_CELL_SIZE: f32 = 6.0;
+const CHUNK: i64 = 64;
+// больше кусков не держим, чтобы кэш не рос бесконечно
+const MAX_CHUNKS: usize = 4096;
+
+pub fn rgba8(color: [f32; 4]) -> [u8; 4] {
+    [
+        (color[0] * 255.0) as u8,
+        (color[1] * 255.0) as u8,
+        (color[2] * 255.0) as u8,
+        (color[3] * 255.0) as u8,
+    ]
+}
+
+pub struct ChunkCache {
+    // какие куски уже лежат картинками у того, кто рисует кадры
+    known: HashSet<(i64, i64)>,
+    dirty: HashSet<(i64, i64)>,
+    // картинки у рисующего надо выбросить: кэш сброшен или ходок новый
+    reset: bool,
+}
+
+impl Default for ChunkCache {
+    fn default() -> ChunkCache {
+        ChunkCache::new()
+    }
+}
+
+impl ChunkCache {
+    pub fn new() -> ChunkCache {
+        ChunkCache {
+            known: HashSet::new(),
+            dirty: HashSet::new(),
+            reset: true,
+        }
+    }
+
+    pub fn invalidate(&mut self, board_i: i64, board_j: i64) {
+        let chunk = (board_i.div_euclid(CHUNK), board_j.div_euclid(CHUNK));
+        if self.known.contains(&chunk) {
+            self.dirty.insert(chunk);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.known.clear();
+        self.dirty.clear();
+        self.reset = true;
+    }
+
+    // видимые куски в кадр; пиксели считаем только для новых и изменившихся
+    pub fn frame<F>(&mut self, frame: &mut Frame, camera: &Camera, pixel: F)
+    where F: Fn(i64, i64) -> [u8; 4] {
+        let top_left = camera.screen_to_board(0, 0);
+        let bottom_right = camera.screen_to_board(camera.dim.0, camera.dim.1);
+        let chunks_i = top_left.0.div_euclid(CHUNK)..=bottom_right.0.div_euclid(CHUNK);
+        let chunks_j = top_left.1.div_euclid(CHUNK)..=bottom_right.1.div_euclid(CHUNK);
+
+        if self.known.len() > MAX_CHUNKS {
+            self.clear();
+        }
+        frame.reset_chunks |= std::mem::replace(&mut self.reset, false);
+
+        for ci in chunks_i {
+            for cj in chunks_j.clone() {
+                let chunk = (ci, cj);
+                let pixels = if !self.known.contains(&chunk) || self.dirty.contains(&chunk) {
+                    let mut rgba = Vec::with_capacity((CHUNK * CHUNK * 4) as usize);
+                    for j in 0..CHUNK {
+                        for i in 0..CHUNK {
+                            rgba.extend_from_slice(&pixel(ci * CHUNK + i, cj * CHUNK + j));
+                        }
+                    }
+                    self.known.insert(chunk);
+                    self.dirty.remove(&chunk);
+                    Some(rgba)
+                } else {
+                    None
+                };
+
+                let (screen_i, screen_j) = camera.board_to_screen(ci * CHUNK, cj * CHUNK);
+                frame.chunks.push(Chunk {
+                    chunk,
+                    pixels,
+                    param: graphics::DrawParam::default()
+                    .dest(na::Point2::new(screen_i as f32 * camera.cell_size, screen_j as f32 * camera.cell_size))
+                    .scale(na::Vector2::new(camera.cell_size, camera.cell_size)),
+                });
+            }
+        }
+    }
+}
+
+// кусок доски в кадре; пиксели есть, только если картинку надо сделать заново
+pub struct Chunk {
+    chunk: (i64, i64),
+    pixels: Option<Vec<u8>>,
+    param: graphics::DrawParam,
+}
+
+// картинки кусков у того, кто рисует кадры; что в них лежит, помнит ChunkCache ходока
+pub struct ChunkImages {
+    images: HashMap<(i64, i64), graphics::Image>,
+}
+
+impl Default for ChunkImages {
+    fn default() -> ChunkImages {
+        ChunkImages::new()
+    }
+}
+
+impl ChunkImages {
+    pub fn new() -> ChunkImages {
+        ChunkImages {
+            images: HashMap::new(),
+        }
+    }
+}
+
+// Кадр ходока: собирается под замком симуляции — это всё, что нужно знать о доске, —
+// а рисуется уже без замка, так что даже полная перерисовка не держит рабочий поток.
+// Экран между кадрами не стирается, в кадре только то, что изменилось.
+pub struct Frame {
+    // залить экран перед рисованием
+    pub clear: Option<[f32; 4]>,
+    pub reset_chunks: bool,
+    // мелкий масштаб: куски доски картинками
+    pub chunks: Vec<Chunk>,
+    // клетки, уже разложенные по батчам, по порядку рисования
+    pub batches: Vec<MeshBatch>,
+    // отдельные меши поверх всего: ходок и его метки
+    pub meshes: Vec<(Mesh, graphics::DrawParam)>,
+}
+
+impl Default for Frame {
+    fn default() -> Frame {
+        Frame::new()
+    }
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame {
+            clear: None,
+            reset_chunks: false,
+            chunks: vec!(),
+            batches: vec!(),
+            meshes: vec!(),
+        }
+    }
+
+    // возвращает батчи уже пустыми, чтобы ходок разложил по ним следующий кадр
+    pub fn draw(self, ctx: &mut Context, images: &mut ChunkImages) -> GameResult<Vec<MeshBatch>> {
+        if let Some(color) = self.clear {
+            graphics::clear(ctx, color.into());
+        }
+
+        if self.reset_chunks {
+            images.images.clear();
+        }
+        for chunk in self.chunks {
+            if let Some(pixels) = chunk.pixels {
+                let mut image = graphics::Image::from_rgba8(ctx, CHUNK as u16, CHUNK as u16, &pixels)?;
+                image.set_filter(graphics::FilterMode::Nearest);
+                images.images.insert(chunk.chunk, image);
+            }
+            if let Some(image) = images.images.get(&chunk.chunk) {
+                graphics::draw(ctx, image, chunk.param)?;
+            }
+        }
+
+        let mut batches = self.batches;
+        for batch in batches.iter_mut() {
+            batch.draw(ctx, graphics::DrawParam::default())?;
+            batch.clear();
+        }
+        for (mesh, param) in self.meshes.iter() {
+            graphics::draw(ctx, mesh, *param)?;
+        }
+
+        Ok(batches)
+    }
+}
+
+// меши ходока под один размер клетки и одну тему
+pub trait WalkerAssets {
+    fn new(cell_size: f32, theme: &Theme, ctx: &mut Context) -> Self;
+
+    // меши, которые рисуются батчами, по порядку рисования
+    fn batched(&self) -> Vec<Mesh>;
+
+    // метка ходока в мелком масштабе
+    fn marker(&self) -> &Mesh;
+}
+
+// что перерисовать на следующем кадре
+pub enum Dirty {
+    Some(Vec<(i64, i64)>),
+    All,
+}
+
+// Экран ходока: камера, тема, меши под них и батчи, куда раскладываются клетки кадра,
+// и что с прошлого кадра надо перерисовать. Как рисовать саму клетку, знает ходок.
+pub struct Screen<A> {
+    pub assets: A,
+    // в порядке WalkerAssets::batched; пока кадр рисуется, батчи у рисующего
+    pub batches: Vec<MeshBatch>,
+    pub camera: Camera,
+    pub theme: Theme,
+    dirty: Dirty,
+    // ходока надо нарисовать заново
+    walker: bool,
+}
+
+impl<A: WalkerAssets> Screen<A> {
+    // dim клеток по короткой стороне окна
+    pub fn new(resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> Screen<A> {
+        let camera = Camera::new(resolution, resolution.0.min(resolution.1) / dim as f32, (0, 0));
+        let assets = A::new(camera.cell_size, theme, ctx);
+
+        Screen {
+            batches: batches(&assets),
+            assets,
+            camera,
+            theme: theme.clone(),
+            dirty: Dirty::All,
+            walker: true,
+        }
+    }
+
+    // меши зависят от размера клетки и темы, так что после зума их надо пересобрать
+    fn rebuild(&mut self, ctx: &mut Context) {
+        self.assets = A::new(self.camera.cell_size, &self.theme, ctx);
+        self.batches = batches(&self.assets);
+        self.redraw();
+    }
+
+    // размер клетки задаёт зум, а окно просто вмещает больше или меньше клеток
+    pub fn resize(&mut self, resolution: (f32, f32), ctx: &mut Context) {
+        self.camera.resize(resolution);
+        self.rebuild(ctx);
+    }
+
+    pub fn set_theme(&mut self, theme: &Theme, ctx: &mut Context) {
+        self.theme = theme.clone();
+        self.rebuild(ctx);
+    }
+
+    pub fn zoom(&mut self, factor: f32, around: (f32, f32), ctx: &mut Context) {
+        if self.camera.zoom(factor, around) {
+            self.rebuild(ctx);
+        }
+    }
+
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        if self.camera.pan(dx, dy) {
+            self.redraw();
+        }
+    }
+
+    pub fn toggle_follow(&mut self, coord: (i64, i64)) {
+        if self.camera.toggle_follow(coord) {
+            self.redraw();
+        }
+    }
+
+    // ходок сдвинулся: камера едет за ним, а его самого надо перерисовать
+    pub fn track(&mut self, coord: (i64, i64)) {
+        if self.camera.track(coord) {
+            self.dirty = Dirty::All;
+        }
+        self.walker = true;
+    }
+
+    pub fn redraw(&mut self) {
+        self.dirty = Dirty::All;
+        self.walker = true;
+    }
+
+    // доска та же, а ходок повернулся или его клетку перекрасили
+    pub fn redraw_walker(&mut self) {
+        self.walker = true;
+    }
+
+    pub fn mark(&mut self, screen_i: i64, screen_j: i64) {
+        if let Dirty::Some(ref mut v) = &mut self.dirty {
+            v.push((screen_i, screen_j));
+        }
+    }
+
+    // клетка доски, а не экрана
+    pub fn mark_cell(&mut self, coord: (i64, i64)) {
+        let (screen_i, screen_j) = self.camera.board_to_screen(coord.0, coord.1);
+        self.mark(screen_i, screen_j);
+    }
+
+    // на больших скоростях список грязных клеток длиннее самого экрана
+    pub fn limit_dirty(&mut self) {
+        let overflow = match &self.dirty {
+            Dirty::Some(v) => v.len() as i64 > self.camera.dim.0 * self.camera.dim.1,
+            _ => false,
+        };
+        if overflow {
+            self.dirty = Dirty::All;
+        }
+    }
+
+    // левый верхний угол клетки экрана
+    pub fn at(&self, screen_i: i64, screen_j: i64) -> graphics::DrawParam {
+        let cell_size = self.camera.cell_size;
+        graphics::DrawParam::default()
+        .dest(na::Point2::new(screen_i as f32 * cell_size, screen_j as f32 * cell_size))
+    }
+
+    // батчи, которые отдали с прошлым кадром; если меши с тех пор пересобраны, у экрана уже новые
+    pub fn recycle(&mut self, batches: Vec<MeshBatch>) {
+        if self.batches.is_empty() {
+            self.batches = batches;
+        }
+    }
+
+    // клетки, которые перерисовать в этом кадре; перед полной перерисовкой экран заливается
+    pub fn dirty(&mut self, frame: &mut Frame) -> Vec<(i64, i64)> {
+        // батчи прошлого кадра не вернулись: рисование сорвалось
+        if self.batches.is_empty() {
+            self.batches = batches(&self.assets);
+        }
+
+        match std::mem::replace(&mut self.dirty, Dirty::Some(vec!())) {
+            Dirty::Some(cells) => cells,
+            Dirty::All => {
+                frame.clear = Some(self.theme.paper);
+                let (w, h) = self.camera.dim;
+                (0..w).flat_map(|i| (0..h).map(move |j| (i, j))).collect()
+            },
+        }
+    }
+
+    // разложенные батчи уходят в кадр; true — пора рисовать ходока
+    pub fn finish(&mut self, frame: &mut Frame) -> bool {
+        frame.batches = std::mem::take(&mut self.batches);
+        std::mem::replace(&mut self.walker, false)
+    }
+
+    pub fn lod(&self) -> bool {
+        self.camera.cell_size < LOD_CELL_SIZE
+    }
+
+    // кадр мелкого масштаба с меткой ходока; куски доски в него кладёт сам ходок
+    pub fn lod_frame(&mut self, coord: (i64, i64)) -> Frame {
+        let mut frame = Frame::new();
+        frame.clear = Some(self.theme.paper);
+
+        let (screen_i, screen_j) = self.camera.board_to_screen(coord.0, coord.1);
+        frame.meshes.push((self.assets.marker().clone(), self.at(screen_i, screen_j)));
+
+        // при возврате к крупным клеткам всё перерисуем целиком
+        self.redraw();
+
+        frame
+    }
+}
+
+fn batches<A: WalkerAssets>(assets: &A) -> Vec<MeshBatch> {
+    assets.batched().into_iter().map(|mesh| MeshBatch::new(mesh).unwrap()).collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Board,
+    Visits,
+    Recency,
+    Flips,
+}
+
+impl RenderMode {
+    pub fn next(self) -> RenderMode {
+        match self {
+            RenderMode::Board => RenderMode::Visits,
+            RenderMode::Visits => RenderMode::Recency,
+            RenderMode::Recency => RenderMode::Flips,
+            RenderMode::Flips => RenderMode::Board,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RenderMode::Board => "board",
+            RenderMode::Visits => "visits",
+            RenderMode::Recency => "recent changes",
+            RenderMode::Flips => "flip count",
+        }
+    }
+}
+
+// сколько раз ходок стоял в каждой клетке
+pub struct Visits {
+    counts: HashMap<(i64, i64), u64>,
+    // верх шкалы, степень двойки
+    pub max: u64,
+}
+
+impl Default for Visits {
+    fn default() -> Visits {
+        Visits::new()
+    }
+}
+
+impl Visits {
+    pub fn new() -> Visits {
+        Visits {
+            counts: HashMap::new(),
+            max: 1,
+        }
+    }
+
+    // true, если шкала выросла и всё надо перекрасить
+    pub fn visit(&mut self, coord: (i64, i64)) -> bool {
+        let count = self.counts.entry(coord).or_insert(0);
+        *count += 1;
+        if *count > self.max {
+            self.max = count.next_power_of_two();
+            return true;
+        }
+
+        false
+    }
+
+    pub fn unvisit(&mut self, coord: (i64, i64)) {
+        let gone = match self.counts.get_mut(&coord) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => false,
+        };
+        if gone {
+            self.counts.remove(&coord);
+        }
+    }
+
+    pub fn get(&self, coord: (i64, i64)) -> u64 {
+        self.counts.get(&coord).cloned().unwrap_or(0)
+    }
+}
+
+// когда последний раз и сколько раз менялась клетка или мост
+pub struct Changes<K> {
+    changes: HashMap<K, (u64, u64)>,
+    // верх шкалы числа переключений, степень двойки
+    pub max_flips: u64,
+}
+
+impl<K: std::hash::Hash + Eq> Default for Changes<K> {
+    fn default() -> Changes<K> {
+        Changes::new()
+    }
+}
+
+impl<K: std::hash::Hash + Eq> Changes<K> {
+    pub fn new() -> Changes<K> {
+        Changes {
+            changes: HashMap::new(),
+            max_flips: 1,
+        }
+    }
+
+    // true, если шкала переключений выросла и всё надо перекрасить
+    pub fn record(&mut self, key: K, time: u64) -> bool {
+        let change = self.changes.entry(key).or_insert((time, 0));
+        change.0 = time;
+        change.1 += 1;
+        if change.1 > self.max_flips {
+            self.max_flips = change.1.next_power_of_two();
+            return true;
+        }
+
+        false
+    }
+
+    // шаг назад отменяет переключение, но клетка всё равно только что поменялась
+    pub fn undo(&mut self, key: K, time: u64) {
+        if let Some(change) = self.changes.get_mut(&key) {
+            change.0 = time;
+            change.1 = change.1.saturating_sub(1);
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<(u64, u64)> {
+        self.changes.get(key).cloned()
+    }
+}
+
+// свежие изменения яркие, старые выцветают к фону, шкала логарифмическая
+pub fn recency_color(changed: u64, now: u64, fresh: [f32; 4], faded: [f32; 4]) -> [f32; 4] {
+    let age = now.saturating_sub(changed);
+    let t = 1.0 - ((age as f32).ln_1p() / (now.max(1) as f32).ln_1p()).min(1.0);
+
+    let (a, b) = (faded, fresh);
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t, a[3] + (b[3] - a[3]) * t]
+}
+
+// логарифмическая шкала от чёрного через красный и жёлтый к белому
+pub fn heat_color(value: u64, max: u64) -> [f32; 4] {
+    const STOPS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [0.8, 0.0, 0.0],
+        [1.0, 0.85, 0.0],
+        [1.0, 1.0, 1.0],
+    ];
+
+    let t = ((value as f32).ln_1p() / (max.max(1) as f32).ln_1p()).min(1.0);
+    let x = t * (STOPS.len() - 1) as f32;
+    let k = (x.floor() as usize).min(STOPS.len() - 2);
+    let f = x - k as f32;
+
+    let (a, b) = (STOPS[k], STOPS[k + 1]);
+    [a[0] + (b[0] - a[0]) * f, a[1] + (b[1] - a[1]) * f, a[2] + (b[2] - a[2]) * f, 1.0]
+}
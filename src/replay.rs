@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+
+// всё, что человек может сделать с сессией; пишется в запись и из неё же проигрывается
+#[derive(Clone, PartialEq)]
+pub enum Action {
+    Play,
+    Pause,
+    Step,
+    StepBack,
+    Speed(i32),
+    // 0 — сбросить цель
+    RunUntil(u64),
+    Follow,
+    Theme,
+    Render,
+    Hud,
+    Plots,
+    LogLog,
+    Menu,
+    Symmetry,
+    Switch(String),
+    // настройка одной строчкой, как в Setup::to_line
+    Setup(String),
+    // расписание одной строчкой, как в Schedule::to_line
+    Schedule(String),
+    // карта правил одной строчкой, как в RuleMap::to_line
+    Rules(String),
+    Zoom(f32, f32, f32),
+    Pan(f32, f32),
+    Toggle(f32, f32),
+    Place(f32, f32),
+    Resize(f32, f32),
+    // сохранить доску, чтобы и при проигрывании файл появился на том же шаге
+    Export,
+}
+
+impl Action {
+    // строчка файла записи без номера шага
+    pub fn to_line(&self) -> String {
+        match self {
+            Action::Play => String::from("play"),
+            Action::Pause => String::from("pause"),
+            Action::Step => String::from("step"),
+            Action::StepBack => String::from("step-back"),
+            Action::Speed(e) => format!("speed {}", e),
+            Action::RunUntil(target) => format!("run-until {}", target),
+            Action::Follow => String::from("follow"),
+            Action::Theme => String::from("theme"),
+            Action::Render => String::from("render"),
+            Action::Hud => String::from("hud"),
+            Action::Plots => String::from("plots"),
+            Action::LogLog => String::from("log-log"),
+            Action::Menu => String::from("menu"),
+            Action::Symmetry => String::from("symmetry"),
+            Action::Switch(name) => format!("switch {}", name),
+            Action::Setup(setup) => format!("setup {}", setup),
+            Action::Schedule(schedule) => format!("schedule {}", schedule),
+            Action::Rules(map) => format!("rules {}", map),
+            Action::Zoom(factor, x, y) => format!("zoom {} {} {}", factor, x, y),
+            Action::Pan(dx, dy) => format!("pan {} {}", dx, dy),
+            Action::Toggle(x, y) => format!("toggle {} {}", x, y),
+            Action::Place(x, y) => format!("place {} {}", x, y),
+            Action::Resize(w, h) => format!("resize {} {}", w, h),
+            Action::Export => String::from("export"),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Action, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or("empty action")?;
+        let args: Vec<&str> = words.collect();
+
+        let float = |k: usize| -> Result<f32, String> {
+            args.get(k).ok_or(format!("`{}` needs more arguments", name))?
+            .parse().map_err(|_| format!("bad number in `{}`", line))
+        };
+        let int = || -> Result<i64, String> {
+            args.first().ok_or(format!("`{}` needs an argument", name))?
+            .parse().map_err(|_| format!("bad number in `{}`", line))
+        };
+
+        let action = match name {
+            "play" => Action::Play,
+            "pause" => Action::Pause,
+            "step" => Action::Step,
+            "step-back" => Action::StepBack,
+            "speed" => Action::Speed(int()? as i32),
+            "run-until" => Action::RunUntil(int()?.max(0) as u64),
+            "follow" => Action::Follow,
+            "theme" => Action::Theme,
+            "render" => Action::Render,
+            "hud" => Action::Hud,
+            "plots" => Action::Plots,
+            "log-log" => Action::LogLog,
+            "menu" => Action::Menu,
+            "symmetry" => Action::Symmetry,
+            // имя ходока может быть с пробелами
+            "switch" => Action::Switch(args.join(" ")),
+            "setup" => Action::Setup(args.join(" ")),
+            "schedule" => Action::Schedule(args.join(" ")),
+            "rules" => Action::Rules(args.join(" ")),
+            "zoom" => Action::Zoom(float(0)?, float(1)?, float(2)?),
+            "pan" => Action::Pan(float(0)?, float(1)?),
+            "toggle" => Action::Toggle(float(0)?, float(1)?),
+            "place" => Action::Place(float(0)?, float(1)?),
+            "resize" => Action::Resize(float(0)?, float(1)?),
+            "export" => Action::Export,
+            _ => return Err(format!("unknown action `{}`", name)),
+        };
+
+        Ok(action)
+    }
+}
+
+// файл записи: строчки «номер_шага действие аргументы»
+pub struct Replay {
+    pub path: String,
+    pub actions: VecDeque<(u64, Action)>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Replay {
+        let mut actions = VecDeque::new();
+        let content = std::fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            String::new()
+        });
+
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parsed = line.split_once(' ')
+                .ok_or_else(|| String::from("expected a step and an action"))
+                .and_then(|(step, action)| Ok((
+                    step.parse().map_err(|_| format!("bad step `{}`", step))?,
+                    Action::parse(action)?,
+                )));
+            match parsed {
+                Ok(entry) => actions.push_back(entry),
+                Err(e) => eprintln!("{}:{}: {}", path, number + 1, e),
+            }
+        }
+
+        Replay {
+            path: String::from(path),
+            actions,
+        }
+    }
+}
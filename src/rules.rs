@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+
+use super::*;
+
+// где действует правило из карты
+#[derive(Clone, PartialEq)]
+pub enum Area {
+    Region(Region),
+    // клетки, где a·x + b·y >= c
+    HalfPlane(i64, i64, i64),
+    // тёмные пиксели картинки с левым верхним углом в этой клетке
+    Mask(String, (i64, i64), HashSet<(i64, i64)>),
+}
+
+impl Area {
+    fn contains(&self, cell: (i64, i64)) -> bool {
+        match self {
+            Area::Region(region) => region.contains(cell),
+            Area::HalfPlane(a, b, c) => a * cell.0 + b * cell.1 >= *c,
+            Area::Mask(_, _, cells) => cells.contains(&cell),
+        }
+    }
+
+    fn to_words(&self) -> String {
+        match self {
+            Area::Region(region) => region.to_words(),
+            Area::HalfPlane(a, b, c) => format!("half {} {} {}", a, b, c),
+            Area::Mask(path, at, _) => format!("mask {} {} {}", path, at.0, at.1),
+        }
+    }
+}
+
+// Карта правил: своё правило в каждой области, вне областей — правило самого ходока.
+//
+//   LLRR in rect -20 -20 20 20
+//   RRL in disk 40 0 10
+//   LR in half 1 0 60
+//   RL in mask walls.png -32 -32
+//
+// half a b c — клетки, где a·x + b·y >= c; у маски берутся тёмные пиксели.
+// Области могут перекрываться, тогда действует та, что ниже.
+// Муравью нужны правила из двух букв: поворот на белой и на чёрной клетке,
+// инженеру тоже из двух: поворот после постройки моста и после перехода по нему.
+#[derive(Clone, PartialEq)]
+pub struct RuleMap {
+    pub regions: Vec<(Area, String)>,
+}
+
+impl RuleMap {
+    pub fn load(path: &str) -> Option<RuleMap> {
+        let text = std::fs::read_to_string(path).map_err(|e| eprintln!("{}: {}", path, e)).ok()?;
+        RuleMap::parse(&text).map_err(|e| eprintln!("{}: {}", path, e)).ok()
+    }
+
+    pub fn parse(text: &str) -> Result<RuleMap, String> {
+        let regions = parse_lines(text, RuleMap::parse_region)?;
+        if regions.is_empty() {
+            return Err(String::from("the rule map is empty"));
+        }
+        Ok(RuleMap {
+            regions,
+        })
+    }
+
+    fn parse_region(words: &Words) -> Result<(Area, String), String> {
+        let line = words.line;
+        let int = |k: usize| words.int(k);
+
+        let rule = words[0];
+        if !is_rule(rule) {
+            return Err(format!("`{}` is not a rule like RL or LLRR", rule));
+        }
+        if words.get(1) != Some(&"in") {
+            return Err(format!("expected `RULE in AREA`, got `{}`", line));
+        }
+
+        let area = match words.get(2) {
+            Some(&"rect") => Area::Region(Region::Rect((int(3)?, int(4)?), (int(5)?, int(6)?))),
+            Some(&"disk") => Area::Region(Region::Disk((int(3)?, int(4)?), int(5)?)),
+            Some(&"half") => Area::HalfPlane(int(3)?, int(4)?, int(5)?),
+            Some(&"mask") => {
+                let path = words.get(3).ok_or(format!("`{}` needs a file", line))?;
+                let at = (int(4)?, int(5)?);
+                let cells = read_image(path, at, 2).map_err(|e| format!("{}: {}", path, e))?
+                .into_iter()
+                .filter(|&(_, state)| state == 1)
+                .map(|(cell, _)| cell)
+                .collect();
+                Area::Mask(path.to_string(), at, cells)
+            },
+            _ => return Err(format!("expected `rect`, `disk`, `half` or `mask` in `{}`", line)),
+        };
+
+        Ok((area, String::from(rule)))
+    }
+
+    // муравью и инженеру нужны правила ровно из стольких букв
+    pub fn check_letters(&self, walker: &str, letters: usize) -> Result<(), String> {
+        match self.regions.iter().find(|(_, rule)| rule.len() != letters) {
+            Some((_, rule)) => Err(format!("the {} needs {}-letter rules, `{}` has {}", walker, letters, rule, rule.len())),
+            None => Ok(()),
+        }
+    }
+
+    // номер последней области, в которую попала клетка
+    pub fn region_at(&self, cell: (i64, i64)) -> Option<usize> {
+        self.regions.iter().rposition(|(area, _)| area.contains(cell))
+    }
+
+    pub fn rule_at(&self, cell: (i64, i64)) -> Option<&str> {
+        self.region_at(cell).map(|k| self.regions[k].1.as_str())
+    }
+
+    pub fn to_line(&self) -> String {
+        self.regions.iter()
+        .map(|(area, rule)| format!("{} in {}", rule, area.to_words()))
+        .collect::<Vec<_>>().join("; ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_map_reads_every_area() {
+        let map = RuleMap::parse("
+            LLRR in rect -20 -20 20 20   # середина
+            RRL in disk 40 0 10; LR in half 1 0 60
+        ").unwrap();
+
+        assert!(map.regions == vec!(
+            (Area::Region(Region::Rect((-20, -20), (20, 20))), String::from("LLRR")),
+            (Area::Region(Region::Disk((40, 0), 10)), String::from("RRL")),
+            (Area::HalfPlane(1, 0, 60), String::from("LR")),
+        ));
+        assert!(RuleMap::parse(&map.to_line()).unwrap() == map);
+    }
+
+    #[test]
+    fn rule_map_lower_regions_win() {
+        let map = RuleMap::parse("RL in rect 0 0 10 10\nLLRR in disk 10 10 3\nLR in half 1 0 9").unwrap();
+        assert_eq!(map.rule_at((0, 0)), Some("RL"));
+        // диск перекрывает прямоугольник, а полуплоскость x >= 9 — их обоих
+        assert_eq!(map.rule_at((8, 10)), Some("LLRR"));
+        assert_eq!(map.rule_at((9, 10)), Some("LR"));
+        assert_eq!(map.rule_at((100, -100)), Some("LR"));
+        assert_eq!(map.rule_at((-1, 0)), None);
+        assert_eq!(map.region_at((8, 10)), Some(1));
+    }
+
+    #[test]
+    fn rule_map_errors() {
+        assert_eq!(RuleMap::parse("").err().unwrap(), "the rule map is empty");
+        assert_eq!(RuleMap::parse("RL at rect 0 0 1 1").err().unwrap(), "1: expected `RULE in AREA`, got `RL at rect 0 0 1 1`");
+        assert_eq!(RuleMap::parse("RL in rect 0 0 1\n").err().unwrap(), "1: `RL in rect 0 0 1` needs more arguments");
+        assert_eq!(RuleMap::parse("RL in disk 0 0 1\nQQ in disk 0 0 1").err().unwrap(), "2: `QQ` is not a rule like RL or LLRR");
+        assert!(RuleMap::parse("RL in square 0 0 1").is_err());
+        assert!(RuleMap::parse("RL in mask no-such-file.png 0 0").is_err());
+    }
+
+    #[test]
+    fn rule_map_letter_check() {
+        let map = RuleMap::parse("RL in disk 0 0 1; LLR in disk 5 5 1").unwrap();
+        assert_eq!(map.check_letters("ant", 2).err().unwrap(), "the ant needs 2-letter rules, `LLR` has 3");
+        assert!(RuleMap::parse("RL in disk 0 0 1").unwrap().check_letters("ant", 2).is_ok());
+    }
+}
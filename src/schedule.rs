@@ -0,0 +1,150 @@
+use super::*;
+
+// когда заканчивается фаза расписания
+#[derive(Clone, Copy, PartialEq)]
+pub enum Condition {
+    Step(u64),
+    PopulationAbove(usize),
+    PopulationBelow(usize),
+}
+
+impl Condition {
+    pub fn label(self) -> String {
+        match self {
+            Condition::Step(n) => format!("step {}", n),
+            Condition::PopulationAbove(n) => format!("population > {}", n),
+            Condition::PopulationBelow(n) => format!("population < {}", n),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Phase {
+    pub rule: String,
+    // None — правило действует до конца
+    pub until: Option<Condition>,
+}
+
+// Расписание правил: фазы по очереди, каждая до своего условия.
+//
+//   RL until 5000
+//   LLRR until population > 3000
+//   RL
+//
+// until N — то же, что until step N; в одну строчку фазы пишутся через точку с запятой
+#[derive(Clone, PartialEq)]
+pub struct Schedule {
+    pub phases: Vec<Phase>,
+}
+
+impl Schedule {
+    pub fn load(path: &str) -> Option<Schedule> {
+        let text = std::fs::read_to_string(path).map_err(|e| eprintln!("{}: {}", path, e)).ok()?;
+        Schedule::parse(&text).map_err(|e| eprintln!("{}: {}", path, e)).ok()
+    }
+
+    pub fn parse(text: &str) -> Result<Schedule, String> {
+        let phases = parse_lines(text, Schedule::parse_phase)?;
+        if phases.is_empty() {
+            return Err(String::from("the schedule is empty"));
+        }
+        Ok(Schedule {
+            phases,
+        })
+    }
+
+    fn parse_phase(words: &Words) -> Result<Phase, String> {
+        let line = words.line;
+
+        let rule = words[0];
+        if !is_rule(rule) {
+            return Err(format!("`{}` is not a rule like RL or LLRR", rule));
+        }
+
+        let until = match (words.get(1), words.get(2), words.get(3)) {
+            (None, _, _) => None,
+            (Some(&"until"), Some(&"population"), Some(&">")) => Some(Condition::PopulationAbove(words.number(4)?)),
+            (Some(&"until"), Some(&"population"), Some(&"<")) => Some(Condition::PopulationBelow(words.number(4)?)),
+            (Some(&"until"), Some(&"step"), _) => Some(Condition::Step(words.number(3)?)),
+            (Some(&"until"), _, _) => Some(Condition::Step(words.number(2)?)),
+            _ => return Err(format!("expected `RULE until step N` or `RULE until population > N`, got `{}`", line)),
+        };
+
+        Ok(Phase {
+            rule: String::from(rule),
+            until,
+        })
+    }
+
+    pub fn to_line(&self) -> String {
+        self.phases.iter().map(|phase| match phase.until {
+            Some(until) => format!("{} until {}", phase.rule, until.label()),
+            None => phase.rule.clone(),
+        }).collect::<Vec<_>>().join("; ")
+    }
+}
+
+// строчка поворотов вроде RL или LLRR
+pub fn is_rule(rule: &str) -> bool {
+    rule.len() >= 2 && rule.chars().all(|c| "LRNU".contains(c))
+}
+
+// на сколько четвертей по часовой поворачивает буква правила
+pub fn quarters(turn: char) -> u8 {
+    match turn {
+        'R' => 1,
+        'U' => 2,
+        'L' => 3,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_reads_every_condition() {
+        let schedule = Schedule::parse("
+            RL until 5000   # классика
+            LLRR until step 20000
+            RLR until population > 3000; LRRL until population < 10
+            RL
+        ").unwrap();
+
+        let until: Vec<Option<Condition>> = schedule.phases.iter().map(|phase| phase.until).collect();
+        assert!(until == vec!(
+            Some(Condition::Step(5000)),
+            Some(Condition::Step(20000)),
+            Some(Condition::PopulationAbove(3000)),
+            Some(Condition::PopulationBelow(10)),
+            None,
+        ));
+        let rules: Vec<&str> = schedule.phases.iter().map(|phase| phase.rule.as_str()).collect();
+        assert_eq!(rules, vec!("RL", "LLRR", "RLR", "LRRL", "RL"));
+    }
+
+    #[test]
+    fn schedule_survives_its_own_line() {
+        let schedule = Schedule::parse("RL until 5000\nLLRR until population > 3000\nRL").unwrap();
+        assert_eq!(schedule.to_line(), "RL until step 5000; LLRR until population > 3000; RL");
+        assert!(Schedule::parse(&schedule.to_line()).unwrap() == schedule);
+    }
+
+    #[test]
+    fn schedule_errors_name_the_line() {
+        assert_eq!(Schedule::parse("# пусто\n").err().unwrap(), "the schedule is empty");
+        assert_eq!(Schedule::parse("RL\nRX until 5").err().unwrap(), "2: `RX` is not a rule like RL or LLRR");
+        assert_eq!(Schedule::parse("R until 5").err().unwrap(), "1: `R` is not a rule like RL or LLRR");
+        assert_eq!(Schedule::parse("RL until").err().unwrap(), "1: `RL until` needs more arguments");
+        assert_eq!(Schedule::parse("RL until population > -3").err().unwrap(), "1: bad number in `RL until population > -3`");
+        assert!(Schedule::parse("RL after 5").is_err());
+    }
+
+    #[test]
+    fn rule_letters() {
+        assert!(is_rule("RL") && is_rule("LLRR") && is_rule("NU"));
+        assert!(!is_rule("R") && !is_rule("") && !is_rule("RLX") && !is_rule("rl"));
+        assert_eq!("LNRU".chars().map(quarters).collect::<Vec<_>>(), vec!(3, 0, 1, 2));
+    }
+}
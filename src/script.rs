@@ -0,0 +1,595 @@
+use ggez::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use nalgebra as na;
+use ggez::graphics::{self, DrawMode, Mesh, MeshBatch};
+use rhai::{CallFnOptions, Engine, Map, Scope, AST};
+
+use crate::lib::*;
+
+// файл с правилом, лежит рядом с themes.cfg
+pub const SCRIPT: &str = "walker.rhai";
+
+// сколько шагов назад можно откатить
+const HISTORY: usize = 1 << 20;
+
+// правило ходока: по состоянию клетки, месту и направлению решает, что делать дальше;
+// cell — состояние клетки под ходоком, 0 — пустая;
+// x, y — клетка, в которой стоит ходок;
+// heading — 0 вверх, 1 вправо, 2 вниз, 3 влево.
+// None — правило сломалось, ходок стоит на месте
+// правило считается в рабочем потоке симуляции
+pub trait Rule: Send {
+    fn call(&mut self, cell: i64, x: i64, y: i64, heading: i64) -> Option<Move>;
+
+    // подхватить изменения, если правило умеет перечитываться
+    fn reload_if_changed(&mut self) {}
+
+    // сколько состояний клетки у правила, если это известно заранее
+    fn colors(&self) -> Option<i64> {
+        None
+    }
+}
+
+pub struct Move {
+    pub cell: i64,
+    // на сколько четвертей повернуть по часовой, отрицательные — против
+    pub turn: i64,
+    pub forward: bool,
+}
+
+// скрипт с правилом ходока:
+// fn step(cell, x, y, heading) -> #{ cell: новое состояние, turn: поворот, move: шагать ли }
+struct Script {
+    path: PathBuf,
+    engine: Engine,
+    ast: Option<AST>,
+    scope: Scope<'static>,
+    modified: Option<SystemTime>,
+    // после ошибки скрипта ходок стоит, пока файл не поправят
+    failed: bool,
+}
+
+impl Script {
+    fn load(path: &str) -> Script {
+        let mut engine = Engine::new();
+        // зациклившийся скрипт не должен вешать окно
+        engine.set_max_operations(100_000);
+
+        let mut script = Script {
+            path: PathBuf::from(path),
+            engine,
+            ast: None,
+            scope: Scope::new(),
+            modified: None,
+            failed: false,
+        };
+        script.reload();
+        script
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    fn reload(&mut self) {
+        self.modified = self.modified();
+
+        // старое правило остаётся в силе, пока новое не скомпилируется
+        let ast = match self.engine.compile_file(self.path.clone()) {
+            Ok(ast) => ast,
+            Err(e) => {
+                eprintln!("{}: {}", self.path.display(), e);
+                return;
+            },
+        };
+
+        // верхний уровень скрипта выполняем один раз, чтобы его константы были видны в step
+        let mut scope = Scope::new();
+        if let Err(e) = self.engine.run_ast_with_scope(&mut scope, &ast) {
+            eprintln!("{}: {}", self.path.display(), e);
+            return;
+        }
+
+        self.ast = Some(ast);
+        self.scope = scope;
+        self.failed = false;
+    }
+}
+
+impl Rule for Script {
+    fn reload_if_changed(&mut self) {
+        if self.modified() != self.modified {
+            self.reload();
+        }
+    }
+
+    fn call(&mut self, cell: i64, x: i64, y: i64, heading: i64) -> Option<Move> {
+        if self.failed {
+            return None;
+        }
+        let ast = self.ast.as_ref()?;
+
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine
+        .call_fn_with_options::<Map>(options, &mut self.scope, ast, "step", (cell, x, y, heading))
+        .map_err(|e| e.to_string())
+        .and_then(|map| {
+            let int = |key: &str, default: i64| match map.get(key) {
+                Some(value) => value.as_int().map_err(|t| format!("`{}` is {}, expected an integer", key, t)),
+                None => Ok(default),
+            };
+            let forward = match map.get("move") {
+                Some(value) => value.as_bool().map_err(|t| format!("`move` is {}, expected a bool", t))?,
+                None => true,
+            };
+
+            Ok(Move {
+                cell: int("cell", cell)?,
+                turn: int("turn", 0)?,
+                forward,
+            })
+        });
+
+        match result {
+            Ok(m) => Some(m),
+            Err(e) => {
+                eprintln!("{}: {}", self.path.display(), e);
+                self.failed = true;
+                None
+            },
+        }
+    }
+}
+
+// правило-строчка вроде LLRR: на клетке в состоянии c поворот по c-й букве
+// (L налево, R направо, N прямо, U назад), а клетка переходит в c + 1 по кругу;
+// состояния от более длинного правила берутся по модулю
+pub struct Turmite {
+    turns: Vec<i64>,
+}
+
+impl Turmite {
+    pub fn parse(rule: &str) -> Option<Turmite> {
+        if !is_rule(rule) {
+            return None;
+        }
+        Some(Turmite {
+            turns: rule.chars().map(|c| quarters(c) as i64).collect(),
+        })
+    }
+}
+
+impl Rule for Turmite {
+    fn call(&mut self, cell: i64, _x: i64, _y: i64, _heading: i64) -> Option<Move> {
+        let n = self.turns.len() as i64;
+        let cell = cell.rem_euclid(n);
+        Some(Move {
+            cell: (cell + 1) % n,
+            turn: self.turns[cell as usize],
+            forward: true,
+        })
+    }
+
+    fn colors(&self) -> Option<i64> {
+        Some(self.turns.len() as i64)
+    }
+}
+
+struct Assets {
+    tile: Mesh,
+    grid: Mesh,
+    arrow: Mesh,
+    marker: Mesh,
+}
+
+// клетки копятся в батчах: плитка цвета состояния, сетка поверх неё
+const TILE: usize = 0;
+const GRID: usize = 1;
+
+pub struct ScriptWalker {
+    screen: Screen<Assets>,
+    rule: Box<dyn Rule>,
+    // карта правил и тьюрмит для каждой её области
+    rule_map: Option<RuleMap>,
+    regions: Vec<Turmite>,
+    // ненулевые состояния клеток
+    board: HashMap<(i64, i64), i64>,
+    // откуда пришли, куда смотрели и что было в клетке, для шага назад
+    history: Vec<((i64, i64), i64, i64)>,
+    chunks: ChunkCache,
+    explored: Bounds,
+    visits: Visits,
+    changes: Changes<(i64, i64)>,
+    time: u64,
+    drawn_at: u64,
+    render: RenderMode,
+    coord: (i64, i64),
+    heading: i64,
+}
+
+impl ScriptWalker {
+    fn board_to_screen(&self, board_i: i64, board_j: i64) -> (i64, i64) {
+        self.screen.camera.board_to_screen(board_i, board_j)
+    }
+
+    fn screen_to_board(&self, screen_i: i64, screen_j: i64) -> (i64, i64) {
+        self.screen.camera.screen_to_board(screen_i, screen_j)
+    }
+
+    fn cell(&self, coord: (i64, i64)) -> i64 {
+        self.board.get(&coord).cloned().unwrap_or(0)
+    }
+
+    fn set_cell(&mut self, coord: (i64, i64), state: i64) {
+        if state == 0 {
+            self.board.remove(&coord);
+        } else {
+            self.board.insert(coord, state);
+        }
+    }
+
+    fn draw_cell(&mut self, screen_i: i64, screen_j: i64) {
+        let color = self.tint(self.screen_to_board(screen_i, screen_j));
+        let param = self.screen.at(screen_i, screen_j);
+
+        self.screen.batches[TILE].add(param.color(color.into()));
+        self.screen.batches[GRID].add(param);
+    }
+
+    // у скрипта может быть сколько угодно состояний, первые берём из темы
+    fn state_color(&self, state: i64) -> [f32; 4] {
+        let theme = &self.screen.theme;
+        match state {
+            0 => theme.paper,
+            1 => theme.ink,
+            2 => theme.roads,
+            3 => theme.grid,
+            n => heat_color(n.unsigned_abs(), 16),
+        }
+    }
+
+    fn tint(&self, cell: (i64, i64)) -> [f32; 4] {
+        let theme = &self.screen.theme;
+        match self.render {
+            RenderMode::Board => self.state_color(self.cell(cell)),
+            RenderMode::Visits => match self.visits.get(cell) {
+                0 => theme.paper,
+                n => heat_color(n, self.visits.max),
+            },
+            RenderMode::Recency => match self.changes.get(&cell) {
+                Some((changed, _)) => recency_color(changed, self.time, theme.walker, theme.paper),
+                None => theme.paper,
+            },
+            RenderMode::Flips => match self.changes.get(&cell) {
+                Some((_, flips)) if flips > 0 => heat_color(flips, self.changes.max_flips),
+                _ => theme.paper,
+            },
+        }
+    }
+
+    fn draw_walker(&self, frame: &mut Frame) {
+        let cell_size = self.screen.camera.cell_size;
+        let (screen_i, screen_j) = self.board_to_screen(self.coord.0, self.coord.1);
+
+        frame.meshes.push((self.screen.assets.arrow.clone(), graphics::DrawParam::default()
+        .dest(na::Point2::new((screen_i as f32 + 0.5) * cell_size, (screen_j as f32 + 0.5) * cell_size))
+        .rotation(self.heading as f32 * std::f32::consts::FRAC_PI_2)));
+    }
+
+    // клетка поменялась: чистим кэш и запоминаем изменение
+    fn touch(&mut self, coord: (i64, i64)) {
+        self.chunks.invalidate(coord.0, coord.1);
+        if self.changes.record(coord, self.time) && self.render == RenderMode::Flips {
+            self.chunks.clear();
+            self.screen.redraw();
+        }
+    }
+
+    fn draw_lod(&mut self) -> Frame {
+        let mut frame = self.screen.lod_frame(self.coord);
+
+        let mut chunks = std::mem::take(&mut self.chunks);
+        chunks.frame(&mut frame, &self.screen.camera, |i, j| rgba8(self.tint((i, j))));
+        self.chunks = chunks;
+
+        frame
+    }
+}
+
+impl WalkerAssets for Assets {
+    fn new(cell_size: f32, theme: &Theme, ctx: &mut Context) -> Assets {
+        // белая плитка, которую красим в цвет состояния
+        let tile = ggez::graphics::MeshBuilder::new()
+        .rectangle(
+            DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, cell_size, cell_size),
+            graphics::Color::WHITE,
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        let grid = ggez::graphics::MeshBuilder::new()
+        .rectangle(
+            DrawMode::stroke(theme.grid_thickness),
+            graphics::Rect::new(0.0, 0.0, cell_size, cell_size),
+            theme.grid.into(),
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        // стрелка вверх с центром в начале координат, поворачиваем при рисовании
+        let arrow = ggez::graphics::MeshBuilder::new()
+        .polygon(
+            DrawMode::fill(),
+            &[na::Point2::new(0.0, -cell_size * 0.35),
+              na::Point2::new(cell_size * 0.25, cell_size * 0.25),
+              na::Point2::new(-cell_size * 0.25, cell_size * 0.25),
+            ],
+            theme.walker.into(),
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        let marker = ggez::graphics::MeshBuilder::new()
+        .rectangle(
+            DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, cell_size.max(2.0), cell_size.max(2.0)),
+            theme.walker.into(),
+        ).unwrap()
+        .build(ctx).unwrap();
+
+        Assets {
+            tile,
+            grid,
+            arrow,
+            marker,
+        }
+    }
+
+    fn batched(&self) -> Vec<Mesh> {
+        vec!(self.tile.clone(), self.grid.clone())
+    }
+
+    fn marker(&self) -> &Mesh {
+        &self.marker
+    }
+}
+
+impl ScriptWalker {
+    pub fn load(path: &str, resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> ScriptWalker {
+        ScriptWalker::with_rule(Box::new(Script::load(path)), resolution, dim, theme, ctx)
+    }
+
+    pub fn with_rule(rule: Box<dyn Rule>, resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> ScriptWalker {
+        ScriptWalker {
+            screen: Screen::new(resolution, dim, theme, ctx),
+            rule,
+            rule_map: None,
+            regions: vec!(),
+            board: HashMap::new(),
+            history: vec!(),
+            chunks: ChunkCache::new(),
+            explored: Bounds::new((0, 0)),
+            visits: Visits::new(),
+            changes: Changes::new(),
+            time: 0,
+            drawn_at: 0,
+            render: RenderMode::Board,
+            coord: (0, 0),
+            heading: 0,
+        }
+    }
+}
+
+impl Walker for ScriptWalker {
+    fn new(resolution: (f32, f32), dim: i64, theme: &Theme, ctx: &mut Context) -> Self {
+        ScriptWalker::load(SCRIPT, resolution, dim, theme, ctx)
+    }
+
+    fn resize(&mut self, resolution: (f32, f32), ctx: &mut Context) {
+        self.screen.resize(resolution, ctx);
+    }
+
+    fn set_theme(&mut self, theme: &Theme, ctx: &mut Context) {
+        self.screen.set_theme(theme, ctx);
+        self.chunks.clear();
+    }
+
+    fn zoom(&mut self, factor: f32, around: (f32, f32), ctx: &mut Context) {
+        self.screen.zoom(factor, around, ctx);
+    }
+
+    fn pan(&mut self, dx: f32, dy: f32) {
+        self.screen.pan(dx, dy);
+    }
+
+    fn toggle_follow(&mut self) {
+        self.screen.toggle_follow(self.coord);
+    }
+
+    fn toggle(&mut self, x: f32, y: f32) {
+        let (screen_i, screen_j) = self.screen.camera.pixel_to_screen(x, y);
+        let cell = self.screen_to_board(screen_i, screen_j);
+
+        let state = if self.cell(cell) == 0 { 1 } else { 0 };
+        self.set_cell(cell, state);
+        self.touch(cell);
+        self.screen.mark(screen_i, screen_j);
+        self.screen.redraw_walker();
+    }
+
+    fn place(&mut self, x: f32, y: f32) {
+        let (screen_i, screen_j) = self.screen.camera.pixel_to_screen(x, y);
+        let cell = self.screen_to_board(screen_i, screen_j);
+
+        if cell == self.coord {
+            self.heading = (self.heading + 1) % 4;
+        } else {
+            let (old_i, old_j) = self.board_to_screen(self.coord.0, self.coord.1);
+            self.screen.mark(old_i, old_j);
+            self.coord = cell;
+            self.explored.extend(cell);
+        }
+
+        self.screen.mark(screen_i, screen_j);
+        self.screen.redraw_walker();
+    }
+
+    fn redraw(&mut self) {
+        self.screen.redraw();
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            coord: self.coord,
+            heading: ["up", "right", "down", "left"][self.heading as usize],
+            population: self.board.len(),
+            population_label: "non-empty cells",
+            explored: self.explored,
+        }
+    }
+
+    fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render = mode;
+        self.chunks.clear();
+        self.redraw();
+    }
+
+    fn step(&mut self) -> bool {
+        let cell = self.cell(self.coord);
+        let region = self.rule_map.as_ref().and_then(|map| map.region_at(self.coord));
+        let m = match region {
+            Some(k) => self.regions[k].call(cell, self.coord.0, self.coord.1, self.heading),
+            None => self.rule.call(cell, self.coord.0, self.coord.1, self.heading),
+        };
+        let m = match m {
+            Some(m) => m,
+            None => return false,
+        };
+
+        if self.history.len() == HISTORY {
+            self.history.drain(..HISTORY / 2);
+        }
+        self.history.push((self.coord, self.heading, cell));
+
+        self.screen.mark_cell(self.coord);
+
+        self.time += 1;
+        if m.cell != cell {
+            self.set_cell(self.coord, m.cell);
+            self.touch(self.coord);
+        }
+
+        self.heading = (self.heading + m.turn).rem_euclid(4);
+        if m.forward {
+            let (di, dj) = [(0, -1), (1, 0), (0, 1), (-1, 0)][self.heading as usize];
+            self.coord = (self.coord.0 + di, self.coord.1 + dj);
+        }
+
+        self.screen.track(self.coord);
+        self.screen.mark_cell(self.coord);
+
+        self.explored.extend(self.coord);
+        self.chunks.invalidate(self.coord.0, self.coord.1);
+        if self.visits.visit(self.coord) && self.render == RenderMode::Visits {
+            self.chunks.clear();
+            self.screen.redraw();
+        }
+        self.screen.limit_dirty();
+        true
+    }
+
+    fn step_back(&mut self) -> bool {
+        let (coord, heading, cell) = match self.history.pop() {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        self.visits.unvisit(self.coord);
+        self.chunks.invalidate(self.coord.0, self.coord.1);
+        self.screen.mark_cell(self.coord);
+
+        self.time = self.time.saturating_sub(1);
+        if self.cell(coord) != cell {
+            self.set_cell(coord, cell);
+            self.changes.undo(coord, self.time);
+        }
+        self.coord = coord;
+        self.heading = heading;
+        self.chunks.invalidate(coord.0, coord.1);
+
+        self.screen.track(self.coord);
+        self.screen.mark_cell(self.coord);
+
+        self.screen.limit_dirty();
+        true
+    }
+
+    fn can_step_back(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    // правило-строчку можно подставить вместо любого: скрипта, плагина или другой строчки
+    fn set_rule(&mut self, rule: &str) -> bool {
+        match Turmite::parse(rule) {
+            Some(turmite) => {
+                self.rule = Box::new(turmite);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn set_rule_map(&mut self, map: &RuleMap) -> Result<(), String> {
+        self.regions = map.regions.iter().map(|(_, rule)| Turmite::parse(rule).unwrap()).collect();
+        self.rule_map = Some(map.clone());
+        Ok(())
+    }
+
+    fn set_up(&mut self, setup: &Setup) {
+        // картинки делим на столько цветов, сколько у правила, а у скриптов — на два
+        self.board = setup.layout(self.rule.colors().unwrap_or(2)).cells.into_iter().collect();
+        self.coord = setup.start;
+        self.heading = setup.heading.rem_euclid(4);
+        self.explored = Bounds::new(setup.start);
+        self.screen.camera.center_coord = setup.start;
+        self.chunks.clear();
+        self.redraw();
+    }
+
+    fn cells(&self) -> Option<Vec<((i64, i64), i64)>> {
+        Some(self.board.iter().map(|(&cell, &state)| (cell, state)).collect())
+    }
+
+    fn symmetries(&self) -> Vec<Symmetry> {
+        find_symmetries(self.board.keys().cloned(), self.coord, |cell| self.board.get(&cell).cloned())
+    }
+
+    fn frame(&mut self) -> Frame {
+        // правка файла подхватывается на лету, доска остаётся как была
+        self.rule.reload_if_changed();
+
+        if self.render == RenderMode::Recency && self.drawn_at != self.time {
+            self.chunks.clear();
+            self.screen.redraw();
+        }
+        self.drawn_at = self.time;
+
+        if self.screen.lod() {
+            return self.draw_lod();
+        }
+
+        let mut frame = Frame::new();
+        for (i, j) in self.screen.dirty(&mut frame) {
+            self.draw_cell(i, j);
+        }
+        if self.screen.finish(&mut frame) {
+            self.draw_walker(&mut frame);
+        }
+
+        frame
+    }
+
+    fn recycle(&mut self, batches: Vec<MeshBatch>) {
+        self.screen.recycle(batches);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs;
+
+use crate::ant::{Neighbourhood, Sensing};
+use crate::lib::{config_lines, key_value};
+
+// Файл с правилом чувствующего муравья выглядит так:
+//
+//   neighbourhood = sides
+//   0** = R
+//   1** = L
+//   011 = N keep
+//
+// neighbourhood — under, ahead, sides (слева и справа) или moore (все восемь соседей);
+// слева от = конфигурация: сначала клетка под муравьём, потом соседи по порядку
+// (moore — по часовой, начиная с клетки впереди), 1 — чёрная, 0 — белая, * — любая;
+// справа поворот L, R, N (прямо) или U (назад), keep — не перекрашивать клетку.
+// Поздние строчки перекрывают ранние, чего нет в файле, делается как у обычного муравья.
+pub fn load(path: &str) -> Sensing {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Sensing::classic(Neighbourhood::Under),
+    };
+
+    let lines: Vec<(usize, &str)> = config_lines(&text).collect();
+
+    // окрестность нужна раньше остального: от неё зависит размер таблицы
+    let mut neighbourhood = Neighbourhood::Under;
+    for &(n, line) in lines.iter() {
+        let value = match key_value(line) {
+            Ok(("neighbourhood", value)) => value,
+            _ => continue,
+        };
+        neighbourhood = match *value {
+            ["under"] => Neighbourhood::Under,
+            ["ahead"] => Neighbourhood::Ahead,
+            ["sides"] => Neighbourhood::Sides,
+            ["moore"] => Neighbourhood::Moore,
+            _ => {
+                eprintln!("{}:{}: unknown neighbourhood `{}`", path, n, value.join(" "));
+                continue;
+            },
+        };
+    }
+
+    let mut sensing = Sensing::classic(neighbourhood);
+    for &(n, line) in lines.iter() {
+        if line.starts_with("neighbourhood") {
+            continue;
+        }
+        if let Err(e) = set(&mut sensing, line) {
+            eprintln!("{}:{}: {}", path, n, e);
+        }
+    }
+
+    sensing
+}
+
+fn set(sensing: &mut Sensing, line: &str) -> Result<(), String> {
+    let (pattern, value) = key_value(line)?;
+
+    let cells = 1 + sensing.neighbourhood.offsets().len();
+    if pattern.len() != cells || !pattern.chars().all(|c| c == '0' || c == '1' || c == '*') {
+        return Err(format!("`{}` should be {} characters of 0, 1 or *", pattern, cells));
+    }
+
+    let mut words = value.iter().copied();
+    let turn = match words.next() {
+        Some("L") => 3,
+        Some("N") => 0,
+        Some("R") => 1,
+        Some("U") => 2,
+        other => return Err(format!("unknown turn `{}`", other.unwrap_or(""))),
+    };
+    let flip = match words.next() {
+        None => true,
+        Some("keep") => false,
+        Some(other) => return Err(format!("unknown word `{}`", other)),
+    };
+
+    for (config, decision) in sensing.table.iter_mut().enumerate() {
+        let matches = pattern.chars().enumerate().all(|(k, c)| match c {
+            '0' => config & (1 << k) == 0,
+            '1' => config & (1 << k) != 0,
+            _ => true,
+        });
+        if matches {
+            *decision = (turn, flip);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,403 @@
+use std::collections::{HashMap, HashSet};
+
+use super::*;
+
+// область доски, границы включительно
+#[derive(Clone, Copy, PartialEq)]
+pub enum Region {
+    Rect((i64, i64), (i64, i64)),
+    Disk((i64, i64), i64),
+}
+
+impl Region {
+    // клетки по строчкам сверху вниз, чтобы случайная заливка не зависела от хэшей
+    pub fn cells(self) -> Vec<(i64, i64)> {
+        let (min, max) = match self {
+            Region::Rect(a, b) => ((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1))),
+            Region::Disk(c, r) => ((c.0 - r, c.1 - r), (c.0 + r, c.1 + r)),
+        };
+
+        let mut cells = vec!();
+        for j in min.1..=max.1 {
+            for i in min.0..=max.0 {
+                if self.contains((i, j)) {
+                    cells.push((i, j));
+                }
+            }
+        }
+        cells
+    }
+
+    pub fn contains(self, (i, j): (i64, i64)) -> bool {
+        match self {
+            Region::Rect(a, b) => (a.0.min(b.0)..=a.0.max(b.0)).contains(&i) && (a.1.min(b.1)..=a.1.max(b.1)).contains(&j),
+            Region::Disk(c, r) => (i - c.0).pow(2) + (j - c.1).pow(2) <= r * r,
+        }
+    }
+
+    pub fn to_words(self) -> String {
+        match self {
+            Region::Rect(a, b) => format!("rect {} {} {} {}", a.0, a.1, b.0, b.1),
+            Region::Disk(c, r) => format!("disk {} {} {}", c.0, c.1, r),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Figure {
+    // закрасить каждую клетку с такой вероятностью
+    Random(Region, f64),
+    Fill(Region),
+    Line((i64, i64), (i64, i64)),
+    // клетки шахматки со стороной size
+    Checkerboard(Region, i64),
+    // мосты вдоль каждой spacing-й линии сетки
+    Lattice(Region, i64),
+    // каждый мост внутри области с такой вероятностью
+    Bridges(Region, f64),
+    // картинка левым верхним углом в этой клетке, считая от старта;
+    // тёмное — старшие состояния, прозрачное доску не трогает
+    Image(String, (i64, i64), Option<i64>),
+}
+
+// С чего начинает ходок: фигуры по порядку, откуда и куда он смотрит.
+// Клеточные фигуры инженер понимает как узлы, соединённые с соседями по фигуре.
+// Фигуры закрашивают клетки состоянием 1, картинки — сколькими угодно состояниями.
+#[derive(Clone, PartialEq)]
+pub struct Setup {
+    pub seed: u64,
+    pub start: (i64, i64),
+    // 0 вверх, 1 вправо, 2 вниз, 3 влево
+    pub heading: i64,
+    pub figures: Vec<Figure>,
+}
+
+// что получилось из фигур: непустые клетки с состояниями и мосты
+pub struct Layout {
+    pub cells: Vec<((i64, i64), i64)>,
+    pub bridges: Vec<((i64, i64), (i64, i64))>,
+}
+
+impl Default for Setup {
+    fn default() -> Setup {
+        Setup::new()
+    }
+}
+
+impl Setup {
+    pub fn new() -> Setup {
+        Setup {
+            seed: 0,
+            start: (0, 0),
+            heading: 0,
+            figures: vec!(),
+        }
+    }
+
+    // Файл с начальными условиями выглядит так:
+    //
+    //   seed 42
+    //   start 0 0 right
+    //   random disk 0 0 20 0.3
+    //   fill rect -3 -3 3 3
+    //   line -10 5 10 5
+    //   checkerboard rect 10 10 20 20 2
+    //   lattice rect -8 -8 8 8 4
+    //   bridges disk 0 0 10 0.2
+    //   image seed.png -16 -16 3
+    //
+    // у картинки число в конце — на сколько состояний её делить,
+    // без него — сколько их у правила
+    // в одну строчку то же самое пишется через точку с запятой
+    pub fn load(path: &str) -> Setup {
+        let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            String::new()
+        });
+        Setup::parse(&text).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            Setup::new()
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Setup, String> {
+        let mut setup = Setup::new();
+        parse_lines(text, |words| setup.parse_line(words))?;
+        Ok(setup)
+    }
+
+    fn parse_line(&mut self, words: &Words) -> Result<(), String> {
+        let line = words.line;
+        let int = |k: usize| words.int(k);
+        let float = |k: usize| words.float(k);
+        // область и сколько слов она заняла после имени фигуры
+        let region = || -> Result<(Region, usize), String> {
+            match words.get(1) {
+                Some(&"rect") => Ok((Region::Rect((int(2)?, int(3)?), (int(4)?, int(5)?)), 5)),
+                Some(&"disk") => Ok((Region::Disk((int(2)?, int(3)?), int(4)?), 4)),
+                _ => Err(format!("expected `rect` or `disk` in `{}`", line)),
+            }
+        };
+
+        match words[0] {
+            "seed" => self.seed = int(1)? as u64,
+            "start" => {
+                self.start = (int(1)?, int(2)?);
+                self.heading = match words.get(3) {
+                    None | Some(&"up") => 0,
+                    Some(&"right") => 1,
+                    Some(&"down") => 2,
+                    Some(&"left") => 3,
+                    Some(other) => return Err(format!("unknown heading `{}`", other)),
+                };
+            },
+            "random" => {
+                let (region, k) = region()?;
+                self.figures.push(Figure::Random(region, float(k + 1)?));
+            },
+            "fill" => self.figures.push(Figure::Fill(region()?.0)),
+            "line" => self.figures.push(Figure::Line((int(1)?, int(2)?), (int(3)?, int(4)?))),
+            "checkerboard" => {
+                let (region, k) = region()?;
+                let size = if words.len() > k + 1 { int(k + 1)?.max(1) } else { 1 };
+                self.figures.push(Figure::Checkerboard(region, size));
+            },
+            "lattice" => {
+                let (region, k) = region()?;
+                let spacing = if words.len() > k + 1 { int(k + 1)?.max(1) } else { 1 };
+                self.figures.push(Figure::Lattice(region, spacing));
+            },
+            "bridges" => {
+                let (region, k) = region()?;
+                self.figures.push(Figure::Bridges(region, float(k + 1)?));
+            },
+            "image" => {
+                let path = words.get(1).ok_or(format!("`{}` needs a file", line))?;
+                let colors = if words.len() > 4 { Some(int(4)?.max(2)) } else { None };
+                self.figures.push(Figure::Image(path.to_string(), (int(2)?, int(3)?), colors));
+            },
+            name => return Err(format!("unknown figure `{}`", name)),
+        }
+
+        Ok(())
+    }
+
+    // та же настройка одной строчкой, для файла записи
+    pub fn to_line(&self) -> String {
+        let heading = ["up", "right", "down", "left"][self.heading.rem_euclid(4) as usize];
+        let mut parts = vec!(
+            format!("seed {}", self.seed),
+            format!("start {} {} {}", self.start.0, self.start.1, heading),
+        );
+        for figure in self.figures.iter() {
+            parts.push(match figure {
+                Figure::Random(region, p) => format!("random {} {}", region.to_words(), p),
+                Figure::Fill(region) => format!("fill {}", region.to_words()),
+                Figure::Line(a, b) => format!("line {} {} {} {}", a.0, a.1, b.0, b.1),
+                Figure::Checkerboard(region, size) => format!("checkerboard {} {}", region.to_words(), size),
+                Figure::Lattice(region, spacing) => format!("lattice {} {}", region.to_words(), spacing),
+                Figure::Bridges(region, p) => format!("bridges {} {}", region.to_words(), p),
+                Figure::Image(path, offset, colors) => match colors {
+                    Some(colors) => format!("image {} {} {} {}", path, offset.0, offset.1, colors),
+                    None => format!("image {} {} {}", path, offset.0, offset.1),
+                },
+            });
+        }
+        parts.join("; ")
+    }
+
+    // colors — сколько состояний у клетки в правиле ходока, пустое тоже считается
+    pub fn layout(&self, colors: i64) -> Layout {
+        let mut rng = Rng::new(self.seed);
+        let mut cells = HashMap::new();
+        let mut bridges = HashSet::new();
+
+        for figure in self.figures.iter() {
+            match *figure {
+                Figure::Random(region, p) => for cell in region.cells() {
+                    if rng.chance(p) {
+                        cells.insert(cell, 1);
+                    }
+                },
+                Figure::Fill(region) => cells.extend(region.cells().into_iter().map(|cell| (cell, 1))),
+                Figure::Line(a, b) => cells.extend(line(a, b).into_iter().map(|cell| (cell, 1))),
+                Figure::Checkerboard(region, size) => cells.extend(region.cells().into_iter()
+                    .filter(|&(i, j)| (i.div_euclid(size) + j.div_euclid(size)) % 2 == 0)
+                    .map(|cell| (cell, 1))),
+                Figure::Lattice(region, spacing) => {
+                    let inside: HashSet<(i64, i64)> = region.cells().into_iter().collect();
+                    for &(i, j) in inside.iter() {
+                        if j.rem_euclid(spacing) == 0 && inside.contains(&(i + 1, j)) {
+                            bridges.insert(((i, j), (i + 1, j)));
+                        }
+                        if i.rem_euclid(spacing) == 0 && inside.contains(&(i, j + 1)) {
+                            bridges.insert(((i, j), (i, j + 1)));
+                        }
+                    }
+                },
+                Figure::Bridges(region, p) => {
+                    let cells = region.cells();
+                    let inside: HashSet<(i64, i64)> = cells.iter().cloned().collect();
+                    for &(i, j) in cells.iter() {
+                        for &next in [(i + 1, j), (i, j + 1)].iter() {
+                            if inside.contains(&next) && rng.chance(p) {
+                                bridges.insert(((i, j), next));
+                            }
+                        }
+                    }
+                },
+                Figure::Image(ref path, offset, own_colors) => {
+                    let at = (self.start.0 + offset.0, self.start.1 + offset.1);
+                    match read_image(path, at, own_colors.unwrap_or(colors)) {
+                        Ok(pixels) => for (cell, state) in pixels {
+                            if state == 0 {
+                                cells.remove(&cell);
+                            } else {
+                                cells.insert(cell, state);
+                            }
+                        },
+                        Err(e) => eprintln!("{}: {}", path, e),
+                    }
+                },
+            }
+        }
+
+        let mut cells: Vec<((i64, i64), i64)> = cells.into_iter().collect();
+        let mut bridges: Vec<((i64, i64), (i64, i64))> = bridges.into_iter().collect();
+        cells.sort();
+        bridges.sort();
+        Layout {
+            cells,
+            bridges,
+        }
+    }
+}
+
+// клетки картинки с их состояниями
+pub type Pixels = Vec<((i64, i64), i64)>;
+
+// Пиксели картинки как клетки начиная с at: яркость делится на colors равных долей,
+// белое — пустая клетка, чёрное — старшее состояние; полупрозрачное пропускаем.
+pub fn read_image(path: &str, at: (i64, i64), colors: i64) -> Result<Pixels, String> {
+    let image = image::open(path).map_err(|e| e.to_string())?.to_luma_alpha();
+
+    let mut cells = vec!();
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let [luma, alpha] = pixel.data;
+        if alpha < 128 {
+            continue;
+        }
+        let darkness = 1.0 - luma as f64 / 255.0;
+        let state = ((darkness * colors as f64) as i64).min(colors - 1);
+        cells.push(((at.0 + x as i64, at.1 + y as i64), state));
+    }
+
+    Ok(cells)
+}
+
+// Доска в картинку по рамке непустых клеток: пустое белое, старшее состояние чёрное.
+// Формат по расширению: png, pgm или pbm, в pbm всё непустое чёрное.
+// Возвращает клетку, которая попала в левый верхний угол.
+pub fn write_image(path: &str, cells: &[((i64, i64), i64)]) -> Result<(i64, i64), String> {
+    if cells.is_empty() {
+        return Err(String::from("the board is empty"));
+    }
+
+    let colors = cells.iter().map(|&(_, state)| state).max().unwrap().max(1) + 1;
+    let mut bounds = Bounds::new(cells[0].0);
+    for &(cell, _) in cells.iter() {
+        bounds.extend(cell);
+    }
+    let (w, h) = (bounds.max.0 - bounds.min.0 + 1, bounds.max.1 - bounds.min.1 + 1);
+    let bitmap = path.to_ascii_lowercase().ends_with(".pbm");
+
+    let mut pixels = vec!(255u8; (w * h) as usize);
+    for &((i, j), state) in cells.iter() {
+        let k = (j - bounds.min.1) * w + (i - bounds.min.0);
+        pixels[k as usize] = if bitmap {
+            0
+        } else {
+            255 - (255 * state / (colors - 1)) as u8
+        };
+    }
+
+    image::save_buffer(path, &pixels, w as u32, h as u32, image::ColorType::Gray(8)).map_err(|e| e.to_string())?;
+    Ok(bounds.min)
+}
+
+// клетки отрезка по Брезенхему
+fn line(a: (i64, i64), b: (i64, i64)) -> Vec<(i64, i64)> {
+    let (dx, dy) = ((b.0 - a.0).abs(), -(b.1 - a.1).abs());
+    let (sx, sy) = ((b.0 - a.0).signum(), (b.1 - a.1).signum());
+    let mut err = dx + dy;
+    let mut cell = a;
+
+    let mut cells = vec!(cell);
+    while cell != b {
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            cell.0 += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            cell.1 += sy;
+        }
+        cells.push(cell);
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setup_reads_every_figure() {
+        let setup = Setup::parse("
+            # начало
+            seed 42
+            start 1 -2 right   # смотрит направо
+            random disk 0 0 20 0.3
+            fill rect -3 -3 3 3; line -10 5 10 5
+            checkerboard rect 10 10 20 20 2
+            lattice rect -8 -8 8 8
+            bridges disk 0 0 10 0.2
+            image seed.png -16 -16 3
+        ").unwrap();
+
+        assert_eq!((setup.seed, setup.start, setup.heading), (42, (1, -2), 1));
+        assert!(setup.figures == vec!(
+            Figure::Random(Region::Disk((0, 0), 20), 0.3),
+            Figure::Fill(Region::Rect((-3, -3), (3, 3))),
+            Figure::Line((-10, 5), (10, 5)),
+            Figure::Checkerboard(Region::Rect((10, 10), (20, 20)), 2),
+            Figure::Lattice(Region::Rect((-8, -8), (8, 8)), 1),
+            Figure::Bridges(Region::Disk((0, 0), 10), 0.2),
+            Figure::Image(String::from("seed.png"), (-16, -16), Some(3)),
+        ));
+    }
+
+    #[test]
+    fn setup_survives_its_own_line() {
+        let setup = Setup::parse("seed 7\nstart 0 0 left\nfill disk 3 3 2\ncheckerboard rect 0 0 9 9 3").unwrap();
+        assert!(Setup::parse(&setup.to_line()).unwrap() == setup);
+    }
+
+    #[test]
+    fn setup_defaults_and_empty_text() {
+        let setup = Setup::parse("  # только комментарий\n\n").unwrap();
+        assert!(setup == Setup::new());
+        assert_eq!(Setup::parse("start 5 5").unwrap().heading, 0);
+    }
+
+    #[test]
+    fn setup_errors_name_the_line() {
+        assert_eq!(Setup::parse("seed 1\nstart 1").err().unwrap(), "2: `start 1` needs more arguments");
+        assert_eq!(Setup::parse("seed one").err().unwrap(), "1: bad number in `seed one`");
+        assert_eq!(Setup::parse("seed 1; teleport 3 4").err().unwrap(), "2: unknown figure `teleport`");
+        assert_eq!(Setup::parse("fill square 0 0 1").err().unwrap(), "1: expected `rect` or `disk` in `fill square 0 0 1`");
+        assert_eq!(Setup::parse("start 0 0 sideways").err().unwrap(), "1: unknown heading `sideways`");
+        assert!(Setup::parse("random disk 0 0 5").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::*;
+
+// сколько последних появлений и пропаданий симметрии помним
+const SYMMETRY_LOG: usize = 256;
+// поиск симметрии обходит всю доску, так что в потоке он идёт раз в столько шагов,
+// а по шагу — на каждом; пауза на симметрии может опоздать на эти шаги
+const SYMMETRY_EVERY: u64 = 64;
+
+// симуляция, общая для рабочего потока и отрисовки: кто держит замок, тот и ходит
+pub struct Sim {
+    pub walker: Box<dyn Walker>,
+    pub step: u64,
+    pub mode: Mode,
+    pub target: Option<u64>,
+    // при проигрывании дальше этого шага не бежим, пока не применят записанное действие
+    pub hold_at: Option<u64>,
+    pub watch: Watch,
+    pub symmetry: Vec<Symmetry>,
+    // на каких шагах симметрия менялась и какой стала
+    pub symmetry_log: VecDeque<(u64, Vec<Symmetry>)>,
+    pub schedule: Option<Schedule>,
+    // какая фаза расписания идёт и умеет ли ходок менять правило
+    pub phase: usize,
+    pub ruled: bool,
+    // с какого шага началась каждая фаза после первой, чтобы откатываться назад
+    pub phase_log: Vec<(u64, usize)>,
+    // ходок не смог сделать последний шаг
+    pub stalled: bool,
+}
+
+impl Sim {
+    // шаг вперёд; если симметрия появилась, а велено на ней стоять, — пауза,
+    // а если ходок встал, то пауза без шага
+    pub fn advance(&mut self) {
+        self.stalled = !self.walker.step();
+        if self.stalled {
+            self.target = None;
+            self.mode = Mode::StepByStep;
+            return;
+        }
+        self.step += 1;
+        self.follow_schedule();
+        if self.watch_symmetry() && self.watch == Watch::Pause {
+            self.target = None;
+            self.mode = Mode::StepByStep;
+        }
+    }
+
+    pub fn retreat(&mut self) {
+        if !self.walker.step_back() {
+            return;
+        }
+        self.stalled = false;
+        self.step = self.step.saturating_sub(1);
+
+        let step = self.step;
+        while self.symmetry_log.back().is_some_and(|&(at, _)| at > step) {
+            self.symmetry_log.pop_back();
+        }
+        // откатились за начало фазы — возвращаем прежнее правило
+        if self.phase_log.last().is_some_and(|&(at, _)| at > step) {
+            while self.phase_log.last().is_some_and(|&(at, _)| at > step) {
+                self.phase_log.pop();
+            }
+            self.phase = self.phase_log.last().map_or(0, |&(_, phase)| phase);
+            self.apply_phase();
+        }
+        self.symmetry = match self.watch {
+            Watch::Off => vec!(),
+            _ => self.walker.symmetries(),
+        };
+    }
+
+    // начать расписание с первой фазы
+    pub fn start_schedule(&mut self) {
+        self.phase = 0;
+        self.phase_log.clear();
+        self.apply_phase();
+        self.follow_schedule();
+    }
+
+    pub fn apply_phase(&mut self) {
+        if let Some(schedule) = &self.schedule {
+            self.ruled = self.walker.set_rule(&schedule.phases[self.phase].rule);
+        }
+    }
+
+    // условие текущей фазы выполнено — переходим к следующей, и так сколько выйдет
+    fn follow_schedule(&mut self) {
+        let schedule = match &self.schedule {
+            Some(schedule) => schedule,
+            None => return,
+        };
+
+        while self.phase + 1 < schedule.phases.len() {
+            let met = match schedule.phases[self.phase].until {
+                Some(Condition::Step(n)) => self.step >= n,
+                Some(Condition::PopulationAbove(n)) => self.walker.stats().population > n,
+                Some(Condition::PopulationBelow(n)) => self.walker.stats().population < n,
+                None => false,
+            };
+            if !met {
+                break;
+            }
+
+            self.phase += 1;
+            self.phase_log.push((self.step, self.phase));
+            self.ruled = self.walker.set_rule(&schedule.phases[self.phase].rule);
+        }
+    }
+
+    // true, если появилась симметрия, которой на прошлом шаге не было
+    fn watch_symmetry(&mut self) -> bool {
+        if self.watch == Watch::Off {
+            return false;
+        }
+        if let Mode::Stream(_) = self.mode {
+            if !self.step.is_multiple_of(SYMMETRY_EVERY) {
+                return false;
+            }
+        }
+
+        let symmetry = self.walker.symmetries();
+        if symmetry == self.symmetry {
+            return false;
+        }
+        let appeared = symmetry.iter().any(|s| !self.symmetry.contains(s));
+
+        if self.symmetry_log.len() == SYMMETRY_LOG {
+            self.symmetry_log.pop_front();
+        }
+        self.symmetry_log.push_back((self.step, symmetry.clone()));
+        self.symmetry = symmetry;
+        appeared
+    }
+}
+
+pub type Shared = Arc<(Mutex<Sim>, Condvar)>;
+
+// поток, который гоняет симуляцию в режиме Stream независимо от кадров
+pub struct Worker {
+    pub shared: Shared,
+    quit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    pub fn spawn(sim: Sim) -> Worker {
+        let shared = Arc::new((Mutex::new(sim), Condvar::new()));
+        let quit = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let (shared, quit) = (shared.clone(), quit.clone());
+            thread::spawn(move || work(shared, quit))
+        };
+
+        Worker {
+            shared,
+            quit,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Sim> {
+        self.shared.0.lock().unwrap()
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.quit.store(true, Ordering::Relaxed);
+        self.shared.1.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn work(shared: Shared, quit: Arc<AtomicBool>) {
+    // одна порция шагов держит замок не дольше этого, чтобы отрисовка не ждала
+    const SLICE: Duration = Duration::from_millis(2);
+
+    let (lock, wake) = &*shared;
+    // с какого момента и с какого шага считаем темп, и при какой скорости
+    let mut pace: Option<(Instant, u64, Speed)> = None;
+
+    while !quit.load(Ordering::Relaxed) {
+        let mut sim = lock.lock().unwrap();
+        let streaming = match sim.mode {
+            Mode::Stream(speed) => Some(speed),
+            Mode::StepByStep => None,
+        };
+        let speed = match streaming {
+            Some(speed) => speed,
+            None => {
+                pace = None;
+                // спим до Play, но время от времени проверяем, не пора ли выходить
+                let _ = wake.wait_timeout(sim, Duration::from_millis(100)).unwrap();
+                continue;
+            },
+        };
+
+        let now = Instant::now();
+        let (since, from) = match pace {
+            // шаг назад или смена скорости — темп считаем заново
+            Some((since, from, paced)) if paced == speed && from <= sim.step => (since, from),
+            _ => (now, sim.step),
+        };
+        pace = Some((since, from, speed));
+
+        let mut steps = match speed.per_second() {
+            Some(rate) => (from + ((now - since).as_secs_f64() * rate) as u64).saturating_sub(sim.step),
+            None => u64::MAX,
+        };
+        if let Some(target) = sim.target {
+            steps = steps.min(target.saturating_sub(sim.step));
+        }
+        if let Some(hold_at) = sim.hold_at {
+            steps = steps.min(hold_at.saturating_sub(sim.step));
+        }
+
+        let deadline = now + SLICE;
+        let mut done = 0;
+        while done < steps {
+            sim.advance();
+            done += 1;
+            // встали на симметрии
+            if let Mode::StepByStep = sim.mode {
+                break;
+            }
+            if done % 256 == 0 && Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        // цель могли и перешагнуть, например шагом вручную
+        if sim.target.is_some_and(|target| sim.step >= target) {
+            sim.target = None;
+            sim.mode = Mode::StepByStep;
+        }
+        // не успеваем за темпом — долг не копим, иначе потом рванём рывком
+        if done < steps {
+            pace = None;
+        }
+
+        drop(sim);
+        if done == 0 {
+            thread::sleep(Duration::from_millis(1));
+        } else {
+            thread::yield_now();
+        }
+    }
+}
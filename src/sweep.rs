@@ -0,0 +1,329 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::lib::{find_symmetries, is_rule, quarters, Setup};
+
+// Перебор правил без окна:
+//
+//   cargo run --release -- sweep --len 6 --steps 200000 --out sweep.json
+//
+// правило — строчка поворотов по цвету клетки: L налево, R направо, N прямо, U назад;
+// клетка после ухода муравья переходит в следующий цвет. RL — обычный муравей.
+// Отчёт пишется в JSON, если файл кончается на .json, и в CSV иначе.
+//
+// --rule RL берёт одно правило вместо перебора, --setup файл задаёт начальную доску,
+// а --seeds N прогоняет каждое правило на N зёрнах подряд, начиная с зерна из настройки.
+pub fn run(args: &[String]) {
+    let mut len = 6;
+    let mut steps = 100_000;
+    let mut out = String::from("sweep.csv");
+    let mut rule: Option<String> = None;
+    let mut setup = Setup::new();
+    let mut seeds: u64 = 1;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        let parsed = match (arg.as_str(), value) {
+            ("--len", Some(value)) => value.parse().map(|v| len = v).is_ok(),
+            ("--steps", Some(value)) => value.parse().map(|v| steps = v).is_ok(),
+            ("--out", Some(value)) => {
+                out = value.clone();
+                true
+            },
+            ("--rule", Some(value)) => {
+                rule = Some(value.clone());
+                is_rule(value)
+            },
+            ("--setup", Some(value)) => {
+                setup = Setup::load(value);
+                true
+            },
+            ("--seeds", Some(value)) => value.parse().map(|v| seeds = v).is_ok(),
+            _ => false,
+        };
+        if !parsed {
+            eprintln!("usage: sweep [--len N | --rule LR..] [--steps N] [--setup file] [--seeds N] [--out report.csv|report.json]");
+            return;
+        }
+    }
+
+    let rules = match rule {
+        Some(rule) => vec!(rule),
+        None => rules(len),
+    };
+    // каждое правило на каждом зерне
+    let first_seed = setup.seed;
+    let jobs: Vec<(String, u64)> = rules.iter()
+    .flat_map(|rule| (0..seeds).map(move |k| (rule.clone(), first_seed + k)))
+    .collect();
+    eprintln!("{} rules, {} seeds, {} steps each", rules.len(), seeds, steps);
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec!());
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let k = next.fetch_add(1, Ordering::Relaxed);
+                if k >= jobs.len() {
+                    break;
+                }
+
+                let (rule, seed) = &jobs[k];
+                let setup = Setup {
+                    seed: *seed,
+                    ..setup.clone()
+                };
+                let outcome = classify(rule, &setup, steps);
+                results.lock().unwrap().push((k, outcome));
+
+                if (k + 1).is_multiple_of(1000) {
+                    eprintln!("{}/{}", k + 1, jobs.len());
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|&(k, _)| k);
+
+    let report = if out.ends_with(".json") {
+        json(&jobs, &results)
+    } else {
+        csv(&jobs, &results)
+    };
+    if let Err(e) = fs::write(&out, report) {
+        eprintln!("{}: {}", out, e);
+    }
+}
+
+const TURNS: [char; 4] = ['L', 'R', 'N', 'U'];
+
+// все строчки из TURNS длиной от 2 до len
+fn rules(len: usize) -> Vec<String> {
+    let mut rules = vec!();
+    let mut layer = vec!(String::new());
+    for n in 1..=len {
+        layer = layer.iter()
+        .flat_map(|rule| TURNS.iter().map(move |&turn| format!("{}{}", rule, turn)))
+        .collect();
+        if n >= 2 {
+            rules.extend(layer.iter().cloned());
+        }
+    }
+    rules
+}
+
+struct Turmite {
+    // на сколько четвертей по часовой поворачивать на клетке каждого цвета
+    turns: Vec<u8>,
+    board: HashMap<(i64, i64), u8>,
+    coord: (i64, i64),
+    // 0 вверх, 1 вправо, 2 вниз, 3 влево
+    heading: u8,
+}
+
+impl Turmite {
+    fn new(rule: &str, setup: &Setup) -> Turmite {
+        Turmite {
+            turns: rule.chars().map(quarters).collect(),
+            board: setup.layout(rule.len() as i64).cells.into_iter().map(|(cell, state)| (cell, state as u8)).collect(),
+            coord: setup.start,
+            heading: setup.heading.rem_euclid(4) as u8,
+        }
+    }
+
+    fn step(&mut self) {
+        let color = self.board.get(&self.coord).cloned().unwrap_or(0);
+        self.heading = (self.heading + self.turns[color as usize]) % 4;
+
+        let next = (color + 1) % self.turns.len() as u8;
+        if next == 0 {
+            self.board.remove(&self.coord);
+        } else {
+            self.board.insert(self.coord, next);
+        }
+
+        let (di, dj) = [(0, -1), (1, 0), (0, 1), (-1, 0)][self.heading as usize];
+        self.coord = (self.coord.0 + di, self.coord.1 + dj);
+    }
+}
+
+// самый длинный период шоссе, который ищем
+const MAX_PERIOD: usize = 4096;
+
+struct Outcome {
+    class: &'static str,
+    period: usize,
+    drift: (i64, i64),
+    symmetry: &'static str,
+    population: usize,
+    size: (i64, i64),
+}
+
+fn classify(rule: &str, setup: &Setup, steps: u64) -> Outcome {
+    let mut turmite = Turmite::new(rule, setup);
+    // последние позы муравья: хватит, чтобы увидеть три периода подряд
+    let mut history = VecDeque::with_capacity(3 * MAX_PERIOD);
+    let mut explored = (setup.start, setup.start);
+    let mut explored_halfway = explored;
+
+    for step in 0..steps {
+        turmite.step();
+
+        let (i, j) = turmite.coord;
+        let (min, max) = explored;
+        explored = ((min.0.min(i), min.1.min(j)), (max.0.max(i), max.1.max(j)));
+        if step == steps / 2 {
+            explored_halfway = explored;
+        }
+
+        if history.len() == 3 * MAX_PERIOD {
+            history.pop_front();
+        }
+        history.push_back((turmite.coord, turmite.heading));
+    }
+
+    let (min, max) = explored;
+    let mut outcome = Outcome {
+        class: "chaotic",
+        period: 0,
+        drift: (0, 0),
+        symmetry: "",
+        population: turmite.board.len(),
+        size: (max.0 - min.0 + 1, max.1 - min.1 + 1),
+    };
+
+    if let Some((period, drift)) = period(&history) {
+        outcome.class = if drift == (0, 0) { "cyclic" } else { "highway" };
+        outcome.period = period;
+        outcome.drift = drift;
+    } else if explored == explored_halfway {
+        outcome.class = "bounded";
+    } else {
+        let board = &turmite.board;
+        let symmetries = find_symmetries(board.keys().cloned(), turmite.coord, |cell| board.get(&cell).map(|&color| color as i64));
+        if let Some(symmetry) = symmetries.first() {
+            outcome.class = "symmetric";
+            outcome.symmetry = symmetry.label();
+        }
+    }
+
+    outcome
+}
+
+// наименьший период, с которым хвост истории повторяет сам себя со сдвигом;
+// короткие периоды проверяем на MAX_PERIOD шагах, чтобы не спутать с совпадением,
+// а в короткой истории — на всём, что в ней есть
+fn period(history: &VecDeque<((i64, i64), u8)>) -> Option<(usize, (i64, i64))> {
+    let n = history.len();
+    for p in 1..=MAX_PERIOD.min(n / 3) {
+        let window = (2 * p).max(MAX_PERIOD).min(n - p);
+        let (last, heading) = history[n - 1];
+        let (before, before_heading) = history[n - 1 - p];
+        if heading != before_heading {
+            continue;
+        }
+        let drift = (last.0 - before.0, last.1 - before.1);
+
+        let repeats = (n - window..n).all(|t| {
+            let ((i, j), h) = history[t];
+            let ((pi, pj), ph) = history[t - p];
+            h == ph && (i - pi, j - pj) == drift
+        });
+        if repeats {
+            return Some((p, drift));
+        }
+    }
+
+    None
+}
+
+fn csv(jobs: &[(String, u64)], results: &[(usize, Outcome)]) -> String {
+    let mut lines = vec!(String::from("rule,seed,class,period,drift_x,drift_y,symmetry,population,width,height"));
+    for (k, o) in results {
+        let (rule, seed) = &jobs[*k];
+        lines.push(format!("{},{},{},{},{},{},{},{},{},{}",
+            rule, seed, o.class, o.period, o.drift.0, o.drift.1, o.symmetry, o.population, o.size.0, o.size.1));
+    }
+    lines.join("\n") + "\n"
+}
+
+fn json(jobs: &[(String, u64)], results: &[(usize, Outcome)]) -> String {
+    let entries: Vec<String> = results.iter().map(|(k, o)| format!(
+        "  {{\"rule\": \"{}\", \"seed\": {}, \"class\": \"{}\", \"period\": {}, \"drift\": [{}, {}], \"symmetry\": \"{}\", \"population\": {}, \"size\": [{}, {}]}}",
+        jobs[*k].0, jobs[*k].1, o.class, o.period, o.drift.0, o.drift.1, o.symmetry, o.population, o.size.0, o.size.1,
+    )).collect();
+    format!("[\n{}\n]\n", entries.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // муравей, который с шага start идёт по кругу со сдвигом drift за период
+    fn poses(len: usize, start: usize, p: usize, drift: (i64, i64)) -> VecDeque<((i64, i64), u8)> {
+        (0..len).map(|t| {
+            if t < start {
+                ((t as i64 * 7 % 13, t as i64 * 5 % 11), (t % 3) as u8)
+            } else {
+                let (laps, k) = ((t - start) / p, (t - start) % p);
+                ((laps as i64 * drift.0 + k as i64, laps as i64 * drift.1), (k % 4) as u8)
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn period_finds_the_shortest_repeat() {
+        let history = poses(3 * MAX_PERIOD, 100, 104, (-2, 2));
+        assert_eq!(period(&history), Some((104, (-2, 2))));
+
+        let history = poses(3 * MAX_PERIOD, 100, 6, (0, 0));
+        assert_eq!(period(&history), Some((6, (0, 0))));
+    }
+
+    #[test]
+    fn period_needs_the_repeat_over_the_whole_window() {
+        // повтор начался слишком поздно, чтобы покрыть MAX_PERIOD шагов
+        let history = poses(3 * MAX_PERIOD, 3 * MAX_PERIOD - 1000, 104, (-2, 2));
+        assert_eq!(period(&history), None);
+    }
+
+    #[test]
+    fn period_on_short_histories() {
+        assert_eq!(period(&VecDeque::new()), None);
+        assert_eq!(period(&poses(10, 0, 1, (0, 1))), Some((1, (0, 1))));
+        // история короче трёх периодов
+        assert_eq!(period(&poses(200, 0, 104, (-2, 2))), None);
+        assert_eq!(period(&poses(5000, 2000, 1000, (3, 0))), None);
+    }
+
+    #[test]
+    fn classic_ant_builds_a_highway() {
+        // шоссе начинается около шага 10 000, потом нужно MAX_PERIOD шагов повтора
+        let outcome = classify("RL", &Setup::new(), 20_000);
+        assert_eq!(outcome.class, "highway");
+        assert_eq!(outcome.period, 104);
+        assert_eq!(outcome.drift, (-2, 2));
+    }
+
+    #[test]
+    fn llrr_never_settles() {
+        let outcome = classify("LLRR", &Setup::new(), 20_000);
+        assert_eq!(outcome.class, "chaotic");
+        assert_eq!(outcome.period, 0);
+    }
+
+    #[test]
+    fn short_runs_are_not_periodic() {
+        for &steps in [0, 1, 100, 4000, 5000].iter() {
+            let outcome = classify("RL", &Setup::new(), steps);
+            assert_eq!(outcome.period, 0, "{} steps", steps);
+        }
+    }
+}
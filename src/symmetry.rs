@@ -0,0 +1,158 @@
+#[derive(Clone, Copy, PartialEq)]
+pub enum Symmetry {
+    MirrorX,
+    MirrorY,
+    HalfTurn,
+    QuarterTurn,
+}
+
+impl Symmetry {
+    pub fn label(self) -> &'static str {
+        match self {
+            Symmetry::MirrorX => "left-right mirror",
+            Symmetry::MirrorY => "top-bottom mirror",
+            Symmetry::HalfTurn => "half turn",
+            Symmetry::QuarterTurn => "quarter turn",
+        }
+    }
+}
+
+// на горстке клеток симметрия ничего не значит
+const SYMMETRY_MIN_CELLS: usize = 16;
+
+// Какие отражения и повороты вокруг центра рамки узора оставляют доску на месте.
+// Клетка под ходоком не в счёт: он вот-вот её перекрасит.
+// state — цвет клетки, None — пустая.
+pub fn find_symmetries<I, F>(cells: I, walker: (i64, i64), state: F) -> Vec<Symmetry>
+where I: Iterator<Item = (i64, i64)>, F: Fn((i64, i64)) -> Option<i64> {
+    let near = |cell: (i64, i64)| cell == walker;
+    let cells: Vec<(i64, i64)> = cells.filter(|&cell| !near(cell)).collect();
+    if cells.len() < SYMMETRY_MIN_CELLS {
+        return vec!();
+    }
+
+    let mut min = cells[0];
+    let mut max = cells[0];
+    for &(i, j) in cells.iter() {
+        min = (min.0.min(i), min.1.min(j));
+        max = (max.0.max(i), max.1.max(j));
+    }
+    // удвоенный центр рамки, чтобы не возиться с половинками
+    let (si, sj) = (min.0 + max.0, min.1 + max.1);
+
+    let mut found = vec!();
+    for &symmetry in [Symmetry::MirrorX, Symmetry::MirrorY, Symmetry::HalfTurn, Symmetry::QuarterTurn].iter() {
+        // четверть оборота переводит в себя только квадратную рамку
+        if symmetry == Symmetry::QuarterTurn && max.0 - min.0 != max.1 - min.1 {
+            continue;
+        }
+
+        let image = |(i, j): (i64, i64)| match symmetry {
+            Symmetry::MirrorX => (si - i, j),
+            Symmetry::MirrorY => (i, sj - j),
+            Symmetry::HalfTurn => (si - i, sj - j),
+            Symmetry::QuarterTurn => ((si + sj) / 2 - j, (sj - si) / 2 + i),
+        };
+        let holds = cells.iter().all(|&cell| {
+            let other = image(cell);
+            near(other) || state(other) == state(cell)
+        });
+        if holds {
+            found.push(symmetry);
+        }
+    }
+
+    found
+}
+
+// слежка за симметрией: выключена, отмечает моменты или ещё и ставит на паузу
+#[derive(Clone, Copy, PartialEq)]
+pub enum Watch {
+    Off,
+    Flag,
+    Pause,
+}
+
+impl Watch {
+    pub fn next(self) -> Watch {
+        match self {
+            Watch::Off => Watch::Flag,
+            Watch::Flag => Watch::Pause,
+            Watch::Pause => Watch::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Watch::Off => "off",
+            Watch::Flag => "flagging",
+            Watch::Pause => "pausing",
+        }
+    }
+}
+
+pub fn symmetry_label(symmetry: &[Symmetry]) -> String {
+    if symmetry.is_empty() {
+        String::from("none")
+    } else {
+        symmetry.iter().map(|s| s.label()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // симметрии доски из клеток с цветами, ходок в стороне
+    fn symmetries_of(board: &HashMap<(i64, i64), i64>, walker: (i64, i64)) -> Vec<&'static str> {
+        find_symmetries(board.keys().cloned(), walker, |cell| board.get(&cell).cloned())
+        .iter().map(|s| s.label()).collect()
+    }
+
+    // закрашенный прямоугольник, углы включительно
+    fn filled((x0, y0): (i64, i64), (x1, y1): (i64, i64)) -> HashMap<(i64, i64), i64> {
+        (x0..=x1).flat_map(|i| (y0..=y1).map(move |j| ((i, j), 1))).collect()
+    }
+
+    #[test]
+    fn square_has_every_symmetry() {
+        let board = filled((-2, -2), (2, 2));
+        assert_eq!(symmetries_of(&board, (100, 100)), vec!("left-right mirror", "top-bottom mirror", "half turn", "quarter turn"));
+    }
+
+    #[test]
+    fn rectangle_has_no_quarter_turn() {
+        let board = filled((0, 0), (5, 2));
+        assert_eq!(symmetries_of(&board, (100, 100)), vec!("left-right mirror", "top-bottom mirror", "half turn"));
+    }
+
+    #[test]
+    fn colours_break_symmetry() {
+        // левая половина одного цвета, правая другого: зеркало остаётся только поперёк
+        let mut board = filled((0, 0), (3, 3));
+        for j in 0..4 {
+            board.insert((2, j), 2);
+            board.insert((3, j), 2);
+        }
+        assert_eq!(symmetries_of(&board, (100, 100)), vec!("top-bottom mirror"));
+
+        board.insert((0, 0), 2);
+        assert!(symmetries_of(&board, (100, 100)).is_empty());
+    }
+
+    #[test]
+    fn cell_under_the_walker_does_not_count() {
+        let mut board = filled((-2, -2), (2, 2));
+        board.insert((-2, -2), 3);
+        assert!(symmetries_of(&board, (100, 100)).is_empty());
+        assert_eq!(symmetries_of(&board, (-2, -2)).len(), 4);
+    }
+
+    #[test]
+    fn few_cells_are_never_symmetric() {
+        let board = filled((0, 0), (2, 2));
+        assert!(symmetries_of(&board, (100, 100)).is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+use std::fs;
+
+use crate::lib::{config_lines, key_value, Theme};
+
+// Файл с темами выглядит так:
+//
+//   [dark]
+//   paper = 0.1 0.1 0.12
+//   walker = 1.0 0.3 0.2 1.0
+//   road_thickness = 3
+//
+// чего нет в секции, берётся из бумажной темы
+pub fn load(path: &str) -> Vec<Theme> {
+    let mut themes = vec!(Theme::paper());
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return themes,
+    };
+
+    for (n, line) in config_lines(&text) {
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim().to_string();
+            themes.retain(|theme| theme.name != name);
+
+            let mut theme = Theme::paper();
+            theme.name = name;
+            themes.push(theme);
+            continue;
+        }
+
+        if let Err(e) = set(themes.last_mut().unwrap(), line) {
+            eprintln!("{}:{}: {}", path, n, e);
+        }
+    }
+
+    themes
+}
+
+fn set(theme: &mut Theme, line: &str) -> Result<(), String> {
+    let (key, value) = key_value(line)?;
+    let numbers: Vec<f32> = value.numbers()?;
+
+    let color = || match numbers.len() {
+        3 => Ok([numbers[0], numbers[1], numbers[2], 1.0]),
+        4 => Ok([numbers[0], numbers[1], numbers[2], numbers[3]]),
+        _ => Err(format!("`{}` needs 3 or 4 components", key)),
+    };
+    let number = || match numbers.len() {
+        1 => Ok(numbers[0]),
+        _ => Err(format!("`{}` needs a single number", key)),
+    };
+
+    match key {
+        "paper" => theme.paper = color()?,
+        "grid" => theme.grid = color()?,
+        "ink" => theme.ink = color()?,
+        "roads" => theme.roads = color()?,
+        "walker" => theme.walker = color()?,
+        "grid_thickness" => theme.grid_thickness = number()?,
+        "road_thickness" => theme.road_thickness = number()?,
+        "bridge_thickness" => theme.bridge_thickness = number()?,
+        "node_radius" => theme.node_radius = number()?,
+        _ => return Err(format!("unknown key `{}`", key)),
+    }
+
+    Ok(())
+}